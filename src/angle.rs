@@ -0,0 +1,135 @@
+/// Binary angles and the fixed-point trigonometry that goes with them.
+///
+/// Angles increase from +x towards +y, which is clockwise on screen, since y points down.
+
+use gba::bios;
+
+use core::ops;
+
+use crate::fixed::Fixed;
+
+/// An angle, stored so that a full turn is exactly 0x10000.  Arithmetic wraps around for free.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Angle(u16);
+
+/// A quarter of a sine wave, as raw `Fixed` bits, in steps of 1/256 of a turn.
+static QUARTER_SINE: [i16; 65] = [
+    0, 6, 13, 19, 25, 31, 38, 44,
+    50, 56, 62, 68, 74, 80, 86, 92,
+    98, 104, 109, 115, 121, 126, 132, 137,
+    142, 147, 152, 157, 162, 167, 172, 177,
+    181, 185, 190, 194, 198, 202, 206, 209,
+    213, 216, 220, 223, 226, 229, 231, 234,
+    237, 239, 241, 243, 245, 247, 248, 250,
+    251, 252, 253, 254, 255, 255, 256, 256,
+    256,
+];
+
+/// Look up the sine of a 1/256-turn step, using symmetry to unfold the quarter wave.
+#[inline]
+fn sine_step(step: usize) -> i32 {
+    let step = step & 0xff;
+    (match step >> 6 {
+        0 => QUARTER_SINE[step],
+        1 => QUARTER_SINE[128 - step],
+        2 => -QUARTER_SINE[step - 128],
+        _ => -QUARTER_SINE[256 - step],
+    }) as i32
+}
+
+impl Angle {
+    pub const ZERO: Angle = Angle(0);
+    pub const QUARTER: Angle = Angle(0x4000);
+    pub const HALF: Angle = Angle(0x8000);
+
+    pub const fn from_bits(bits: u16) -> Self {
+        Angle(bits)
+    }
+
+    pub const fn to_bits(self) -> u16 {
+        self.0
+    }
+
+    /// Convert from a number of turns, i.e. 1 is a full rotation.
+    pub fn from_turns(turns: Fixed) -> Self {
+        Angle((turns.to_bits() << (16 - Fixed::FRACTIONAL_BITS)) as u16)
+    }
+
+    pub fn to_turns(self) -> Fixed {
+        Fixed::from_bits((self.0 >> (16 - Fixed::FRACTIONAL_BITS)) as i32)
+    }
+
+    pub fn from_degrees(degrees: i16) -> Self {
+        let degrees = bios::rem(degrees as i32, 360);
+        Angle(bios::div(degrees << 16, 360) as u16)
+    }
+
+    pub fn sin(self) -> Fixed {
+        // Linearly interpolate between table entries, which is cheap and makes small angles
+        // behave a little better
+        let step = (self.0 >> 8) as usize;
+        let frac = (self.0 & 0xff) as i32;
+        let s0 = sine_step(step);
+        let s1 = sine_step(step + 1);
+        Fixed::from_bits(s0 + (((s1 - s0) * frac) >> 8))
+    }
+
+    pub fn cos(self) -> Fixed {
+        (self + Angle::QUARTER).sin()
+    }
+
+    pub fn sin_cos(self) -> (Fixed, Fixed) {
+        (self.sin(), self.cos())
+    }
+
+    /// Angle of the vector (x, y), measured from +x.  Returns zero for the zero vector.
+    pub fn atan2(y: Fixed, x: Fixed) -> Self {
+        // The BIOS only cares about the ratio, but wants both values to fit in an i16
+        let mut y = y.to_bits();
+        let mut x = x.to_bits();
+        while y > i16::max_value() as i32 || y < i16::min_value() as i32 || x > i16::max_value() as i32 || x < i16::min_value() as i32 {
+            y >>= 1;
+            x >>= 1;
+        }
+        if x == 0 && y == 0 {
+            return Angle::ZERO;
+        }
+        Angle(bios::atan2(y as i16, x as i16))
+    }
+}
+
+impl ops::Neg for Angle {
+    type Output = Self;
+
+    fn neg(self) -> Self {
+        Angle(self.0.wrapping_neg())
+    }
+}
+
+impl ops::Add<Angle> for Angle {
+    type Output = Self;
+
+    fn add(self, other: Self) -> Self {
+        Angle(self.0.wrapping_add(other.0))
+    }
+}
+
+impl ops::AddAssign<Angle> for Angle {
+    fn add_assign(&mut self, other: Self) {
+        *self = *self + other;
+    }
+}
+
+impl ops::Sub<Angle> for Angle {
+    type Output = Self;
+
+    fn sub(self, other: Self) -> Self {
+        Angle(self.0.wrapping_sub(other.0))
+    }
+}
+
+impl ops::SubAssign<Angle> for Angle {
+    fn sub_assign(&mut self, other: Self) {
+        *self = *self - other;
+    }
+}
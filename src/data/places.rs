@@ -1,12 +1,19 @@
-pub const TILE_SIZE: usize = 8;
+use crate::geom::{Point, Rect, TilePoint, WorldUnit, WorldWhole, TILE_SIZE, point2, rect};
+use crate::whammo::materials::Material;
+
 pub const MAX_PLACE_DIMENSION: usize = 32;
 
+/// How far below the bottom of a place the kill plane is.
+const KILL_PLANE_MARGIN: WorldWhole = TILE_SIZE * 2;
+
 type CharData = [u8; 2560];
 
 // TODO maybe use Tile8bpp here?
 pub struct Tile {
     pub chars: [usize; 4],
     pub solid: bool,
+    /// What it's like to stand on, if it's solid
+    pub material: Material,
 }
 
 pub struct Tileset {
@@ -14,33 +21,149 @@ pub struct Tileset {
     pub tiles: [Tile; 64],
 }
 
+/// What a place is made of beyond its edges.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Outside {
+    /// An endless solid wall.
+    Solid,
+    /// Nothing at all.
+    Empty,
+    /// Whatever's on the nearest edge, stretched out forever.
+    RepeatEdge,
+}
+
+// Stand-ins for the tiles outside a place; they're never drawn
+static OUTSIDE_SOLID_TILE: Tile = Tile{ chars: [0, 0, 0, 0], solid: true, material: Material::Normal };
+static OUTSIDE_EMPTY_TILE: Tile = Tile{ chars: [0, 0, 0, 0], solid: false, material: Material::Normal };
+
+/// Something in a place that notices when Lexy walks into it.
+#[derive(Clone, Copy, Debug)]
+pub struct PlaceTrigger {
+    /// Area it covers, as (x, y, width, height) in pixels
+    pub area: (WorldWhole, WorldWhole, WorldWhole, WorldWhole),
+    pub kind: TriggerKind,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerKind {
+    /// Respawn at this point from now on
+    Checkpoint(WorldWhole, WorldWhole),
+    /// Respawn at the last checkpoint
+    Hazard,
+}
+
+impl PlaceTrigger {
+    pub fn area(&self) -> Rect {
+        let (x, y, width, height) = self.area;
+        rect(x, y, width, height)
+    }
+}
+
+/// Something solid in a place that isn't a tile, like a crate.  Drawn a char at a time, so it
+/// should line up with chars.
+#[derive(Clone, Copy, Debug)]
+pub struct PlaceProp {
+    /// Area it covers, as (x, y, width, height) in pixels
+    pub area: (WorldWhole, WorldWhole, WorldWhole, WorldWhole),
+    pub material: Material,
+}
+
+impl PlaceProp {
+    pub fn area(&self) -> Rect {
+        let (x, y, width, height) = self.area;
+        rect(x, y, width, height)
+    }
+}
+
 pub struct Place {
     pub tileset: &'static Tileset,
+    /// Size of the place in tiles.  Only this much of `tiles` is actually used.
+    pub width: usize,
+    pub height: usize,
+    pub outside: Outside,
+    /// Where Lexy appears, both at first and after falling out of the world.
+    pub spawn: (WorldWhole, WorldWhole),
+    pub triggers: &'static [PlaceTrigger],
+    pub props: &'static [PlaceProp],
     pub tiles: [[u8; MAX_PLACE_DIMENSION]; MAX_PLACE_DIMENSION],
 }
 
+impl Place {
+    /// Fetch the tile at the given coordinates, which don't have to be inside the place; anywhere
+    /// else is decided by `outside`.
+    pub fn tile_at(&self, point: TilePoint) -> &'static Tile {
+        let (tx, ty) = if self.contains(point) {
+            (point.x as usize, point.y as usize)
+        }
+        else {
+            match self.outside {
+                Outside::Solid => return &OUTSIDE_SOLID_TILE,
+                Outside::Empty => return &OUTSIDE_EMPTY_TILE,
+                // An empty place has no edge to repeat, so there's nothing out there either
+                Outside::RepeatEdge if self.width == 0 || self.height == 0 =>
+                    return &OUTSIDE_EMPTY_TILE,
+                Outside::RepeatEdge => (
+                    clamp_index(point.x, self.width),
+                    clamp_index(point.y, self.height),
+                ),
+            }
+        };
+        // Have to go through the reference to get a 'static borrow out of &self
+        let tileset: &'static Tileset = self.tileset;
+        &tileset.tiles[self.tiles[ty][tx] as usize]
+    }
+
+    /// Whether a tile is actually part of the place.
+    pub fn contains(&self, point: TilePoint) -> bool {
+        0 <= point.x && (point.x as usize) < self.width && 0 <= point.y && (point.y as usize) < self.height
+    }
+
+    pub fn spawn_point(&self) -> Point {
+        point2(self.spawn.0, self.spawn.1)
+    }
+
+    /// Anything whose position is below this has fallen out of the world and should respawn.
+    pub fn kill_plane(&self) -> WorldUnit {
+        WorldUnit::from(self.height as WorldWhole * TILE_SIZE + KILL_PLANE_MARGIN)
+    }
+}
+
+/// Clamp `n` to a valid index into something `len` long, which had better not be empty.
+fn clamp_index(n: i32, len: usize) -> usize {
+    debug_assert!(len > 0, "can't clamp an index into nothing");
+    if n < 0 {
+        0
+    }
+    else if n as usize >= len {
+        len - 1
+    }
+    else {
+        n as usize
+    }
+}
+
 pub static MAIN_CHAR_DATA: CharData = *include_bytes!("../../target/assets/tiles.bin");
 
 macro_rules! dummy_tile (
-  () => { Tile{ chars: [11, 10, 5, 4], solid: true } };
+  () => { Tile{ chars: [11, 10, 5, 4], solid: true, material: Material::Normal } };
 );
 
 pub static MAIN_TILESET: Tileset = Tileset{
     chardata: &MAIN_CHAR_DATA,
     tiles: [
         // 0: empty
-        Tile{ chars: [0, 0, 0, 0], solid: false },
+        Tile{ chars: [0, 0, 0, 0], solid: false, material: Material::Normal },
         // 1: top left
-        Tile{ chars: [8, 9, 16, 17], solid: true },
+        Tile{ chars: [8, 9, 16, 17], solid: true, material: Material::Normal },
         // 2: top middle
-        Tile{ chars: [10, 9, 18, 17], solid: true },
+        Tile{ chars: [10, 9, 18, 17], solid: true, material: Material::Normal },
         // 3: top right
-        Tile{ chars: [10, 11, 18, 19], solid: true },
+        Tile{ chars: [10, 11, 18, 19], solid: true, material: Material::Normal },
         // 4: left
         // FIXME whoops, no actual left
-        Tile{ chars: [24, 31, 16, 23], solid: true },
+        Tile{ chars: [24, 31, 16, 23], solid: true, material: Material::Normal },
         // 5: center
-        Tile{ chars: [7, 7, 7, 7], solid: true },
+        Tile{ chars: [7, 7, 7, 7], solid: true, material: Material::Normal },
         // 6: right
         dummy_tile!(),
         // 7: bottom left
@@ -50,9 +173,9 @@ pub static MAIN_TILESET: Tileset = Tileset{
         // 9: bottom right
         dummy_tile!(),
         // 10: single
-        Tile{ chars: [8, 11, 32, 35], solid: true },
+        Tile{ chars: [8, 11, 32, 35], solid: true, material: Material::Normal },
         // 11: grass bg
-        Tile{ chars: [0, 0, 6, 5], solid: false },
+        Tile{ chars: [0, 0, 6, 5], solid: false, material: Material::Normal },
         dummy_tile!(), dummy_tile!(), dummy_tile!(), dummy_tile!(),
         dummy_tile!(), dummy_tile!(), dummy_tile!(), dummy_tile!(),
         dummy_tile!(), dummy_tile!(), dummy_tile!(), dummy_tile!(),
@@ -72,6 +195,22 @@ pub static MAIN_TILESET: Tileset = Tileset{
 
 pub static TEST_PLACE: Place = Place{
     tileset: &MAIN_TILESET,
+    width: 32,
+    height: 32,
+    outside: Outside::Empty,
+    spawn: (48, 80),
+    triggers: &[
+        // Halfway along, just past the floating blocks
+        PlaceTrigger{ area: (256, 128, 16, 48), kind: TriggerKind::Checkpoint(264, 175) },
+        // A patch of bad grass
+        PlaceTrigger{ area: (352, 168, 32, 8), kind: TriggerKind::Hazard },
+    ],
+    props: &[
+        // A crate in the way, just past where she lands
+        PlaceProp{ area: (96, 160, 16, 16), material: Material::Normal },
+        // A springy pad between the floating blocks and the checkpoint
+        PlaceProp{ area: (176, 168, 32, 8), material: Material::Bouncy(fixed!(0.75)) },
+    ],
     tiles: [
         [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
         [0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0],
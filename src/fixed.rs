@@ -1,117 +1,478 @@
-/// Fixed-point fractional type.
+#![macro_use]
+/// Fixed-point fractional types.
 ///
 /// For various reasons, existing crates won't work.
+///
+/// `FixedPoint` is generic over both its storage integer and its number of fractional bits, but
+/// the actual arithmetic is stamped out per storage type by `impl_fixed_point!` below, since the
+/// GBA cares a great deal about exactly which instructions a multiply turns into.
 
+use arrayvec::ArrayString;
 use euclid::num::{Ceil, Floor, Round, One, Zero};
 use gba::bios;
 
 use core::cmp;
 use core::fmt;
+use core::fmt::Write;
 use core::ops;
+use core::str::FromStr;
 
 #[derive(Clone, Copy, PartialOrd, Ord, PartialEq, Eq)]
-pub struct Fixed(FixedStore);
+pub struct FixedPoint<S, const FRAC: usize>(S);
 
-type FixedStore = i32;
-type FixedWhole = i16;
-type FixedFraction = u16;
+/// 24.8, for world coordinates and more or less everything else.
+pub type I24F8 = FixedPoint<i32, 8>;
+/// 16.16, for accumulators that need precision more than range.
+pub type I16F16 = FixedPoint<i32, 16>;
+/// 8.8, for compact data.  Also the format the hardware uses for affine parameters.
+pub type I8F8 = FixedPoint<i16, 8>;
 
-// XXX this is actually a 16x16 tile, which is a bit misleading
-const TILE_SIZE_BITS: usize = 4;
+/// The default fixed type, used unless there's a good reason to pick another.
+pub type Fixed = I24F8;
 
-impl Fixed {
-    pub const FRACTIONAL_BITS: usize = 8;
-    pub const PRECISION: FixedFraction = 1;
-    pub const MAX_FRACTION: FixedFraction = ((1usize << Self::FRACTIONAL_BITS) - 1) as FixedFraction;
+/// Any fixed type, viewed as plain bits; lets the conversions work between any two of them.
+pub trait FixedBits: Copy {
+    const FRACTIONAL_BITS: usize;
+
+    fn to_wide_bits(self) -> i64;
+}
+
+/// Divide two widened values, going through the BIOS if they fit in an i32, which they nearly
+/// always do.
+#[inline]
+fn wide_div(numer: i64, denom: i64) -> i64 {
+    let (min, max) = (i32::min_value() as i64, i32::max_value() as i64);
+    if min <= numer && numer <= max && min <= denom && denom <= max {
+        bios::div(numer as i32, denom as i32) as i64
+    }
+    else {
+        numer / denom
+    }
+}
+
+/// Move bits from one number of fractional bits to another.  Extra precision is floored away.
+#[inline]
+fn rescale_bits(bits: i64, from: usize, to: usize) -> i64 {
+    if from < to {
+        bits << (to - from)
+    }
+    else {
+        bits >> (from - to)
+    }
+}
 
-    pub fn promote(n: FixedWhole) -> Self {
-        Self((n as FixedStore) << Self::FRACTIONAL_BITS)
+/// Most decimal places that will be computed exactly when formatting or parsing; more than this
+/// is padded with zeroes or ignored.  Any more and the arithmetic doesn't fit in a u64.
+const MAX_DECIMAL_PLACES: usize = 9;
+/// Most decimal places that will be printed at all.
+const MAX_PRINTED_PLACES: usize = 16;
+
+/// Write out a fixed value in decimal.  With no precision, prints exactly as many digits as it
+/// takes to represent the value (which is at most the number of fractional bits); otherwise,
+/// rounds to that many places.
+fn format_fixed(f: &mut fmt::Formatter, bits: i64, frac: usize) -> fmt::Result {
+    // Room for 10 whole digits, a point, and every printable place.  The sign is handled by
+    // pad_integral, which also (unlike pad) doesn't think the precision is a maximum width
+    let mut buf = ArrayString::<[u8; 32]>::new();
+    let mut negative = bits < 0;
+    let magnitude = bits.abs() as u64;
+    let mask = (1u64 << frac) - 1;
+
+    match f.precision() {
+        Some(precision) => {
+            let precision = cmp::min(precision, MAX_PRINTED_PLACES);
+            let exact_places = cmp::min(precision, MAX_DECIMAL_PLACES);
+            let scale = 10u64.pow(exact_places as u32);
+            // Round half away from zero, which is easy since we're working with the magnitude
+            let scaled = ((magnitude * scale) + (1 << frac >> 1)) >> frac;
+            let whole = scaled / scale;
+            let fraction = scaled % scale;
+            // Don't print "-0.00" for tiny negative numbers that round to zero
+            if scaled == 0 {
+                negative = false;
+            }
+            write!(buf, "{}", whole)?;
+            if precision > 0 {
+                write!(buf, ".{:01$}", fraction, exact_places)?;
+                for _ in exact_places .. precision {
+                    buf.push('0');
+                }
+            }
+        }
+        None => {
+            write!(buf, "{}", magnitude >> frac)?;
+            let mut fraction = magnitude & mask;
+            if fraction > 0 {
+                buf.push('.');
+                while fraction > 0 {
+                    fraction *= 10;
+                    write!(buf, "{}", fraction >> frac)?;
+                    fraction &= mask;
+                }
+            }
+        }
     }
 
-    pub const fn from_bits(n: FixedStore) -> Self {
+    f.pad_integral(! negative, "", &buf)
+}
+
+/// Problems that can happen when parsing a fixed value from a string.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ParseFixedError {
+    /// Nothing to parse, or only a sign.
+    Empty,
+    /// Something that isn't a digit, or a second decimal point.
+    InvalidDigit,
+    /// The value doesn't fit in the destination type.
+    Overflow,
+}
+
+/// Parse a decimal string like "-12.375" into fixed bits, rounding to the nearest representable
+/// value (and away from zero on a tie).  The result still needs range-checking for the actual
+/// storage type.  Underscores are ignored, as in Rust literals.
+///
+/// This is a const fn so `fixed!` can use it, hence the slightly old-fashioned style.
+const fn parse_fixed(s: &str, frac: usize) -> Result<i64, ParseFixedError> {
+    let bytes = s.as_bytes();
+    let mut i = 0;
+    let negative = bytes.len() > 0 && bytes[0] == b'-';
+    if bytes.len() > 0 && (bytes[0] == b'-' || bytes[0] == b'+') {
+        i += 1;
+    }
+
+    let mut whole: i64 = 0;
+    let mut numer: u64 = 0;
+    let mut denom: u64 = 1;
+    let mut any_digits = false;
+    let mut seen_point = false;
+    while i < bytes.len() {
+        let byte = bytes[i];
+        i += 1;
+        if byte == b'_' {
+            continue;
+        }
+        if byte == b'.' && ! seen_point {
+            seen_point = true;
+            continue;
+        }
+        if byte < b'0' || byte > b'9' {
+            return Err(ParseFixedError::InvalidDigit);
+        }
+        let digit = (byte - b'0') as u64;
+        any_digits = true;
+        if ! seen_point {
+            whole = match whole.checked_mul(10) {
+                Some(n) if n <= (i64::max_value() >> frac) - 9 => n + digit as i64,
+                _ => return Err(ParseFixedError::Overflow),
+            };
+        }
+        else if denom < 10u64.pow(MAX_DECIMAL_PLACES as u32) {
+            numer = numer * 10 + digit;
+            denom *= 10;
+        }
+    }
+    if ! any_digits {
+        return Err(ParseFixedError::Empty);
+    }
+
+    // Round the fraction to the nearest step; this might carry all the way into the whole part
+    let fraction = ((numer << frac) + denom / 2) / denom;
+    let magnitude = (whole << frac) + fraction as i64;
+    Ok(if negative { -magnitude } else { magnitude })
+}
+
+macro_rules! impl_fixed_point (
+    ($store:ident, $wide:ident, $whole:ident, $fraction:ident) => {
+
+impl<const FRAC: usize> FixedPoint<$store, FRAC> {
+    pub const FRACTIONAL_BITS: usize = FRAC;
+    pub const PRECISION: $fraction = 1;
+    pub const MAX_FRACTION: $fraction = ((1u64 << FRAC) - 1) as $fraction;
+
+    pub const fn promote(n: $whole) -> Self {
+        Self((n as $store) << FRAC)
+    }
+
+    pub const fn from_bits(n: $store) -> Self {
         Self(n)
     }
 
-    pub fn min_value() -> Self {
-        Self(FixedStore::min_value())
+    pub const fn to_bits(self) -> $store {
+        self.0
+    }
+
+    pub const fn min_value() -> Self {
+        Self($store::min_value())
     }
 
-    pub fn max_value() -> Self {
-        Self(FixedStore::max_value())
+    pub const fn max_value() -> Self {
+        Self($store::max_value())
     }
 
-    pub fn max_fraction() -> Self {
-        Self(Self::MAX_FRACTION as FixedStore)
+    pub const fn max_fraction() -> Self {
+        Self(Self::MAX_FRACTION as $store)
     }
 
-    pub fn abs(self) -> Self {
+    pub const fn abs(self) -> Self {
         Self(self.0.abs())
     }
 
+    /// Square root.  Negative numbers have no square root, which panics in debug builds and
+    /// produces zero otherwise; use `checked_sqrt` if that might happen.
     pub fn sqrt(self) -> Self {
-        // XXX what if i'm negative?  i guess this cast will explode then anyway
-        Self((bios::sqrt(self.0 as u32) as i32) >> (Self::FRACTIONAL_BITS / 2))
+        match self.checked_sqrt() {
+            Some(root) => root,
+            None => {
+                debug_assert!(false, "sqrt of negative fixed {:?}", self);
+                Self(0)
+            }
+        }
     }
 
-    pub fn to_int_floor(self) -> FixedWhole {
-        (self.0 >> Self::FRACTIONAL_BITS) as FixedWhole
+    pub fn checked_sqrt(self) -> Option<Self> {
+        if self.0 < 0 {
+            return None;
+        }
+
+        // The root of a fixed value is sqrt(bits * 2**FRAC), but the BIOS only takes a u32, so
+        // big numbers have to do the shift afterwards and lose some precision.  (This also
+        // assumes FRAC is even, which it had better be.)
+        let bits = self.0 as u32;
+        let root = if (bits as u64) << FRAC <= u32::max_value() as u64 {
+            bios::sqrt(bits << FRAC) as u32
+        }
+        else {
+            (bios::sqrt(bits) as u32) << (FRAC / 2)
+        };
+        Some(Self(root as $store))
     }
 
-    pub fn to_int_round(self) -> FixedWhole {
-        (self + Self::max_fraction()).to_int_floor()
+    /// Round towards negative infinity.
+    pub fn floor(self) -> Self {
+        Self(self.0 & !(Self::MAX_FRACTION as $store))
     }
 
-    // TODO maybe this is more appropriate on a typed Length
-    // XXX this is actually a 16x16 tile, which is a bit misleading
-    pub fn to_tile_coord(self) -> usize {
-        // TODO what if i'm negative
-        (self.0 >> (Self::FRACTIONAL_BITS + TILE_SIZE_BITS)) as usize
+    /// Round towards positive infinity.
+    pub fn ceil(self) -> Self {
+        Self(self.0 + Self::MAX_FRACTION as $store).floor()
     }
 
-    // TODO maybe this is more appropriate on a typed Length
-    pub fn to_sprite_offset_x(self) -> u16 {
-        // TODO what if i'm too big or small
-        (self.to_int_round() + 512) as u16 & 0x01ffu16
+    /// Round towards zero.
+    pub fn trunc(self) -> Self {
+        if self.0 < 0 { self.ceil() } else { self.floor() }
     }
-    pub fn to_sprite_offset_y(self) -> u16 {
-        // TODO what if i'm too big or small
-        (self.to_int_round() + 256) as u16 & 0x00ffu16
+
+    /// Round to the nearest integer, and away from zero on a tie, like `f32::round`.
+    pub fn round(self) -> Self {
+        let half = 1 << (FRAC - 1);
+        if self.0 < 0 { Self(self.0 - half).ceil() } else { Self(self.0 + half).floor() }
+    }
+
+    pub fn to_int_floor(self) -> $whole {
+        (self.0 >> FRAC) as $whole
+    }
+
+    pub fn to_int_ceil(self) -> $whole {
+        self.ceil().to_int_floor()
+    }
+
+    pub fn to_int_trunc(self) -> $whole {
+        self.trunc().to_int_floor()
+    }
+
+    pub fn to_int_round(self) -> $whole {
+        self.round().to_int_floor()
+    }
+
+    /// Parse a decimal string, rounding to the nearest representable value.  Also available
+    /// via `str::parse`, or at compile time via `fixed!`.
+    pub const fn from_decimal_str(s: &str) -> Result<Self, ParseFixedError> {
+        match parse_fixed(s, FRAC) {
+            Ok(bits) => match Self::checked_narrow(bits) {
+                Some(value) => Ok(value),
+                None => Err(ParseFixedError::Overflow),
+            },
+            Err(err) => Err(err),
+        }
+    }
+
+    // Conversions between fixed types.  Lossless ones are also available as From impls.
+
+    /// Convert from another fixed type, or return None if the value is out of range.  Extra
+    /// precision is floored away.
+    pub fn checked_from_fixed<T: FixedBits>(other: T) -> Option<Self> {
+        Self::checked_narrow(rescale_bits(other.to_wide_bits(), T::FRACTIONAL_BITS, FRAC))
+    }
+
+    /// Convert from another fixed type, clamping to this type's range.  Extra precision is
+    /// floored away.
+    pub fn saturating_from_fixed<T: FixedBits>(other: T) -> Self {
+        Self::saturating_narrow(rescale_bits(other.to_wide_bits(), T::FRACTIONAL_BITS, FRAC))
+    }
+
+    // Arithmetic with explicit overflow behavior.  The operators panic on overflow in debug
+    // builds (which the panic handler reports to mGBA) and wrap in release builds, same as ints.
+
+    /// Shrink a widened result back down, or return None if it doesn't fit.
+    #[inline]
+    const fn checked_narrow(wide: i64) -> Option<Self> {
+        if wide > $store::max_value() as i64 || wide < $store::min_value() as i64 {
+            None
+        }
+        else {
+            Some(Self(wide as $store))
+        }
+    }
+
+    #[inline]
+    fn saturating_narrow(wide: i64) -> Self {
+        if wide > $store::max_value() as i64 {
+            Self::max_value()
+        }
+        else if wide < $store::min_value() as i64 {
+            Self::min_value()
+        }
+        else {
+            Self(wide as $store)
+        }
+    }
+
+    #[inline]
+    fn wide_mul(self, other: Self) -> $wide {
+        (self.0 as $wide * other.0 as $wide) >> FRAC
+    }
+
+    /// Divide, assuming `other` is nonzero.
+    #[inline]
+    fn wide_div(self, other: Self) -> i64 {
+        wide_div((self.0 as i64) << FRAC, other.0 as i64)
+    }
+
+    pub fn checked_neg(self) -> Option<Self> {
+        self.0.checked_neg().map(Self)
+    }
+
+    pub fn checked_add(self, other: Self) -> Option<Self> {
+        self.0.checked_add(other.0).map(Self)
+    }
+
+    pub fn checked_sub(self, other: Self) -> Option<Self> {
+        self.0.checked_sub(other.0).map(Self)
+    }
+
+    pub fn checked_mul(self, other: Self) -> Option<Self> {
+        Self::checked_narrow(self.wide_mul(other) as i64)
+    }
+
+    pub fn checked_div(self, other: Self) -> Option<Self> {
+        if other.0 == 0 {
+            None
+        }
+        else {
+            Self::checked_narrow(self.wide_div(other))
+        }
+    }
+
+    pub fn checked_rem(self, other: Self) -> Option<Self> {
+        self.0.checked_rem(other.0).map(Self)
+    }
+
+    pub fn saturating_neg(self) -> Self {
+        Self(self.0.saturating_neg())
+    }
+
+    pub fn saturating_add(self, other: Self) -> Self {
+        Self(self.0.saturating_add(other.0))
+    }
+
+    pub fn saturating_sub(self, other: Self) -> Self {
+        Self(self.0.saturating_sub(other.0))
+    }
+
+    pub fn saturating_mul(self, other: Self) -> Self {
+        Self::saturating_narrow(self.wide_mul(other) as i64)
+    }
+
+    /// Like `checked_div`, but dividing by zero saturates towards the sign of `self`.
+    pub fn saturating_div(self, other: Self) -> Self {
+        if other.0 == 0 {
+            if self.0 < 0 { Self::min_value() } else { Self::max_value() }
+        }
+        else {
+            Self::saturating_narrow(self.wide_div(other))
+        }
+    }
+
+    pub fn wrapping_neg(self) -> Self {
+        Self(self.0.wrapping_neg())
+    }
+
+    pub fn wrapping_add(self, other: Self) -> Self {
+        Self(self.0.wrapping_add(other.0))
+    }
+
+    pub fn wrapping_sub(self, other: Self) -> Self {
+        Self(self.0.wrapping_sub(other.0))
+    }
+
+    pub fn wrapping_mul(self, other: Self) -> Self {
+        Self(self.wide_mul(other) as $store)
+    }
+
+    /// Panics on division by zero, same as the integer `wrapping_div`.
+    pub fn wrapping_div(self, other: Self) -> Self {
+        if other.0 == 0 {
+            panic!("fixed division by zero");
+        }
+        Self(self.wide_div(other) as $store)
+    }
+
+    pub fn wrapping_rem(self, other: Self) -> Self {
+        Self(self.0.wrapping_rem(other.0))
+    }
+}
+
+impl<const FRAC: usize> FixedBits for FixedPoint<$store, FRAC> {
+    const FRACTIONAL_BITS: usize = FRAC;
+
+    fn to_wide_bits(self) -> i64 {
+        self.0 as i64
     }
 }
 
-impl fmt::Debug for Fixed {
+/// Prints the exact value by default, or rounds to a number of places if given a precision, as
+/// in `{:.2}`.
+impl<const FRAC: usize> fmt::Display for FixedPoint<$store, FRAC> {
     fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
-        write!(f, "{}", self.to_int_floor())?;
-        // TODO does this work correctly with negatives?  for that matter, does int_floor?  don't i
-        // want to round towards zero?
-        let mut frac = self.0 as FixedFraction & Fixed::MAX_FRACTION;
-        if frac > 0 {
-            write!(f, ".")?;
-            for place in 0..3 {
-                frac *= 10;
-                let digit = (frac & !Fixed::MAX_FRACTION) >> Fixed::FRACTIONAL_BITS;
-                write!(f, "{}", digit)?;
-                frac &= Fixed::MAX_FRACTION;
-                if frac == 0 {
-                    break;
-                }
-            }
-        }
-        Ok(())
+        format_fixed(f, self.0 as i64, FRAC)
+    }
+}
+
+impl<const FRAC: usize> fmt::Debug for FixedPoint<$store, FRAC> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> Result<(), fmt::Error> {
+        format_fixed(f, self.0 as i64, FRAC)
+    }
+}
+
+impl<const FRAC: usize> FromStr for FixedPoint<$store, FRAC> {
+    type Err = ParseFixedError;
+
+    fn from_str(s: &str) -> Result<Self, ParseFixedError> {
+        Self::from_decimal_str(s)
     }
 }
 
 
 // Standard comparison traits
 
-impl cmp::PartialEq<FixedWhole> for Fixed {
-    fn eq(&self, other: &FixedWhole) -> bool {
+impl<const FRAC: usize> cmp::PartialEq<$whole> for FixedPoint<$store, FRAC> {
+    fn eq(&self, other: &$whole) -> bool {
         *self == Self::promote(*other)
     }
 }
 
-impl cmp::PartialOrd<FixedWhole> for Fixed {
-    fn partial_cmp(&self, other: &FixedWhole) -> Option<cmp::Ordering> {
+impl<const FRAC: usize> cmp::PartialOrd<$whole> for FixedPoint<$store, FRAC> {
+    fn partial_cmp(&self, other: &$whole) -> Option<cmp::Ordering> {
         Some(self.0.cmp(&Self::promote(*other).0))
     }
 }
@@ -119,169 +480,182 @@ impl cmp::PartialOrd<FixedWhole> for Fixed {
 
 // Standard math traits (other fixeds)
 
-impl ops::Neg for Fixed {
+impl<const FRAC: usize> ops::Neg for FixedPoint<$store, FRAC> {
     type Output = Self;
 
     fn neg(self) -> Self {
-        Fixed(-self.0)
+        Self(-self.0)
     }
 }
 
-impl ops::Add<Fixed> for Fixed {
+impl<const FRAC: usize> ops::Add<Self> for FixedPoint<$store, FRAC> {
     type Output = Self;
 
     fn add(self, other: Self) -> Self {
-        Fixed(self.0 + other.0)
+        Self(self.0 + other.0)
     }
 }
 
-impl ops::AddAssign<Fixed> for Fixed {
+impl<const FRAC: usize> ops::AddAssign<Self> for FixedPoint<$store, FRAC> {
     fn add_assign(&mut self, other: Self) {
         self.0 += other.0
     }
 }
 
-impl ops::Sub<Fixed> for Fixed {
+impl<const FRAC: usize> ops::Sub<Self> for FixedPoint<$store, FRAC> {
     type Output = Self;
 
     fn sub(self, other: Self) -> Self {
-        Fixed(self.0 - other.0)
+        Self(self.0 - other.0)
     }
 }
 
-impl ops::SubAssign<Fixed> for Fixed {
+impl<const FRAC: usize> ops::SubAssign<Self> for FixedPoint<$store, FRAC> {
     fn sub_assign(&mut self, other: Self) {
         self.0 -= other.0
     }
 }
 
-impl ops::Mul<Fixed> for Fixed {
+impl<const FRAC: usize> ops::Mul<Self> for FixedPoint<$store, FRAC> {
     type Output = Self;
 
     fn mul(self, other: Self) -> Self {
-        Self((self.0 * other.0) >> Self::FRACTIONAL_BITS)
+        if cfg!(debug_assertions) {
+            self.checked_mul(other).expect("fixed multiplication overflowed")
+        }
+        else {
+            self.wrapping_mul(other)
+        }
     }
 }
 
-impl ops::MulAssign<Fixed> for Fixed {
+impl<const FRAC: usize> ops::MulAssign<Self> for FixedPoint<$store, FRAC> {
     fn mul_assign(&mut self, other: Self) {
-        self.0 = self.0 * other.0 >> Self::FRACTIONAL_BITS;
+        *self = *self * other;
     }
 }
 
-impl ops::Div<Fixed> for Fixed {
+impl<const FRAC: usize> ops::Div<Self> for FixedPoint<$store, FRAC> {
     type Output = Self;
 
     fn div(self, other: Self) -> Self {
-        Self(bios::div(self.0 << Self::FRACTIONAL_BITS, other.0))
+        if cfg!(debug_assertions) {
+            if other.0 == 0 {
+                panic!("fixed division by zero");
+            }
+            self.checked_div(other).expect("fixed division overflowed")
+        }
+        else {
+            self.wrapping_div(other)
+        }
     }
 }
 
-impl ops::DivAssign<Fixed> for Fixed {
+impl<const FRAC: usize> ops::DivAssign<Self> for FixedPoint<$store, FRAC> {
     fn div_assign(&mut self, other: Self) {
         *self = *self / other;
     }
 }
 
-impl ops::Rem<Fixed> for Fixed {
+impl<const FRAC: usize> ops::Rem<Self> for FixedPoint<$store, FRAC> {
     type Output = Self;
 
     fn rem(self, other: Self) -> Self {
-        Fixed(bios::rem(self.0, other.0))
+        Self(bios::rem(self.0 as i32, other.0 as i32) as $store)
     }
 }
 
-impl ops::RemAssign<Fixed> for Fixed {
+impl<const FRAC: usize> ops::RemAssign<Self> for FixedPoint<$store, FRAC> {
     fn rem_assign(&mut self, other: Self) {
-        self.0 %= other.0
+        *self = *self % other
     }
 }
 
 
 // Standard math traits (ints)
 
-impl ops::Add<FixedWhole> for Fixed {
-    type Output = Fixed;
+impl<const FRAC: usize> ops::Add<$whole> for FixedPoint<$store, FRAC> {
+    type Output = Self;
 
-    fn add(self, other: FixedWhole) -> Self {
+    fn add(self, other: $whole) -> Self {
         self + Self::promote(other)
     }
 }
 
-impl ops::Add<Fixed> for FixedWhole {
-    type Output = Fixed;
+impl<const FRAC: usize> ops::Add<FixedPoint<$store, FRAC>> for $whole {
+    type Output = FixedPoint<$store, FRAC>;
 
-    fn add(self, other: Fixed) -> Fixed {
-        Fixed::promote(self) + other
+    fn add(self, other: FixedPoint<$store, FRAC>) -> FixedPoint<$store, FRAC> {
+        FixedPoint::<$store, FRAC>::promote(self) + other
     }
 }
 
-impl ops::AddAssign<FixedWhole> for Fixed {
-    fn add_assign(&mut self, other: FixedWhole) {
+impl<const FRAC: usize> ops::AddAssign<$whole> for FixedPoint<$store, FRAC> {
+    fn add_assign(&mut self, other: $whole) {
         *self += Self::promote(other)
     }
 }
 
-impl ops::Sub<FixedWhole> for Fixed {
-    type Output = Fixed;
+impl<const FRAC: usize> ops::Sub<$whole> for FixedPoint<$store, FRAC> {
+    type Output = Self;
 
-    fn sub(self, other: FixedWhole) -> Self {
+    fn sub(self, other: $whole) -> Self {
         self - Self::promote(other)
     }
 }
 
-impl ops::Sub<Fixed> for FixedWhole {
-    type Output = Fixed;
+impl<const FRAC: usize> ops::Sub<FixedPoint<$store, FRAC>> for $whole {
+    type Output = FixedPoint<$store, FRAC>;
 
-    fn sub(self, other: Fixed) -> Fixed {
-        Fixed::promote(self) - other
+    fn sub(self, other: FixedPoint<$store, FRAC>) -> FixedPoint<$store, FRAC> {
+        FixedPoint::<$store, FRAC>::promote(self) - other
     }
 }
 
-impl ops::SubAssign<FixedWhole> for Fixed {
-    fn sub_assign(&mut self, other: FixedWhole) {
+impl<const FRAC: usize> ops::SubAssign<$whole> for FixedPoint<$store, FRAC> {
+    fn sub_assign(&mut self, other: $whole) {
         *self -= Self::promote(other)
     }
 }
 
-impl ops::Mul<FixedWhole> for Fixed {
+impl<const FRAC: usize> ops::Mul<$whole> for FixedPoint<$store, FRAC> {
     type Output = Self;
 
-    fn mul(self, other: FixedWhole) -> Self {
-        Self(self.0 * other as i32)
+    fn mul(self, other: $whole) -> Self {
+        Self(self.0 * other as $store)
     }
 }
 
-impl ops::MulAssign<FixedWhole> for Fixed {
-    fn mul_assign(&mut self, other: FixedWhole) {
+impl<const FRAC: usize> ops::MulAssign<$whole> for FixedPoint<$store, FRAC> {
+    fn mul_assign(&mut self, other: $whole) {
         *self = *self * other;
     }
 }
 
-impl ops::Div<FixedWhole> for Fixed {
+impl<const FRAC: usize> ops::Div<$whole> for FixedPoint<$store, FRAC> {
     type Output = Self;
 
-    fn div(self, other: FixedWhole) -> Self {
-        Self(self.0 / other as FixedStore)
+    fn div(self, other: $whole) -> Self {
+        Self(self.0 / other as $store)
     }
 }
 
-impl ops::DivAssign<FixedWhole> for Fixed {
-    fn div_assign(&mut self, other: FixedWhole) {
-        self.0 /= other as FixedStore;
+impl<const FRAC: usize> ops::DivAssign<$whole> for FixedPoint<$store, FRAC> {
+    fn div_assign(&mut self, other: $whole) {
+        self.0 /= other as $store;
     }
 }
 
-impl ops::Rem<FixedWhole> for Fixed {
+impl<const FRAC: usize> ops::Rem<$whole> for FixedPoint<$store, FRAC> {
     type Output = Self;
 
-    fn rem(self, other: FixedWhole) -> Self {
+    fn rem(self, other: $whole) -> Self {
         self % Self::promote(other)
     }
 }
 
-impl ops::RemAssign<FixedWhole> for Fixed {
-    fn rem_assign(&mut self, other: FixedWhole) {
+impl<const FRAC: usize> ops::RemAssign<$whole> for FixedPoint<$store, FRAC> {
+    fn rem_assign(&mut self, other: $whole) {
         *self %= Self::promote(other)
     }
 }
@@ -289,8 +663,8 @@ impl ops::RemAssign<FixedWhole> for Fixed {
 
 // Standard conversion traits
 
-impl From<FixedWhole> for Fixed {
-    fn from(whole: FixedWhole) -> Self {
+impl<const FRAC: usize> From<$whole> for FixedPoint<$store, FRAC> {
+    fn from(whole: $whole) -> Self {
         Self::promote(whole)
     }
 }
@@ -298,14 +672,320 @@ impl From<FixedWhole> for Fixed {
 
 // Euclid traits
 
-impl Zero for Fixed {
+impl<const FRAC: usize> Zero for FixedPoint<$store, FRAC> {
     fn zero() -> Self {
         Self::promote(0)
     }
 }
 
-impl One for Fixed {
+impl<const FRAC: usize> One for FixedPoint<$store, FRAC> {
     fn one() -> Self {
         Self::promote(1)
     }
 }
+
+impl<const FRAC: usize> Floor for FixedPoint<$store, FRAC> {
+    fn floor(self) -> Self {
+        FixedPoint::<$store, FRAC>::floor(self)
+    }
+}
+
+impl<const FRAC: usize> Ceil for FixedPoint<$store, FRAC> {
+    fn ceil(self) -> Self {
+        FixedPoint::<$store, FRAC>::ceil(self)
+    }
+}
+
+impl<const FRAC: usize> Round for FixedPoint<$store, FRAC> {
+    fn round(self) -> Self {
+        FixedPoint::<$store, FRAC>::round(self)
+    }
+}
+
+    };
+);
+
+impl_fixed_point!(i32, i64, i16, u32);
+impl_fixed_point!(i16, i32, i8, u16);
+
+
+/// Build a fixed value from a decimal literal at compile time, rounding to the nearest
+/// representable value, so tunables can be written out exactly: `fixed!(0.213)`, `fixed!(-3)`.
+/// Defaults to `Fixed`, but another fixed type can be given first: `fixed!(I16F16: 0.001)`.
+/// Literals that are malformed or out of range fail to compile.
+macro_rules! fixed (
+    // Note that the type arms have to come last; a ty fragment can't back out once it's tried
+    (@parse $t:ty, $s:expr) => ({
+        const VALUE: $t = match <$t>::from_decimal_str($s) {
+            Ok(value) => value,
+            Err(_) => panic!("invalid fixed literal"),
+        };
+        VALUE
+    });
+    (- $lit:literal) => { fixed!(@parse $crate::fixed::Fixed, concat!("-", stringify!($lit))) };
+    ($lit:literal) => { fixed!(@parse $crate::fixed::Fixed, stringify!($lit)) };
+    ($t:ty: - $lit:literal) => { fixed!(@parse $t, concat!("-", stringify!($lit))) };
+    ($t:ty: $lit:literal) => { fixed!(@parse $t, stringify!($lit)) };
+);
+
+// Lossless conversions between fixed types
+
+impl From<I8F8> for I24F8 {
+    fn from(n: I8F8) -> Self {
+        Self(n.0 as i32)
+    }
+}
+
+impl From<I8F8> for I16F16 {
+    fn from(n: I8F8) -> Self {
+        Self((n.0 as i32) << 8)
+    }
+}
+
+
+// Stuff specific to world coordinates
+
+impl Fixed {
+    // TODO maybe this is more appropriate on a typed Length
+    pub fn to_sprite_offset_x(self) -> u16 {
+        // TODO what if i'm too big or small
+        (self.to_int_round() + 512) as u16 & 0x01ffu16
+    }
+    pub fn to_sprite_offset_y(self) -> u16 {
+        // TODO what if i'm too big or small
+        (self.to_int_round() + 256) as u16 & 0x00ffu16
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bits(n: i32) -> Fixed {
+        Fixed::from_bits(n)
+    }
+
+    #[test]
+    fn checked_at_limits() {
+        let (max, min) = (Fixed::max_value(), Fixed::min_value());
+        assert_eq!(max.checked_mul(1.into()), Some(max));
+        assert_eq!(max.checked_mul((-1).into()), Some(bits(-i32::max_value())));
+        assert_eq!(max.checked_mul(2.into()), None);
+        assert_eq!(min.checked_mul(1.into()), Some(min));
+        assert_eq!(min.checked_mul((-1).into()), None);
+        assert_eq!(max.checked_mul(fixed!(0.5)), Some(bits(i32::max_value() >> 1)));
+
+        assert_eq!(max.checked_div(1.into()), Some(max));
+        assert_eq!(min.checked_div(1.into()), Some(min));
+        assert_eq!(min.checked_div((-1).into()), None);
+        assert_eq!(max.checked_div(fixed!(0.5)), None);
+        assert_eq!(Fixed::from(1).checked_div(max), Some(bits(0)));
+
+        assert_eq!(Fixed::from(1).checked_div(0.into()), None);
+        assert_eq!(Fixed::from(0).checked_div(0.into()), None);
+        assert_eq!(min.checked_div(0.into()), None);
+
+        assert_eq!(max.checked_add(bits(1)), None);
+        assert_eq!(min.checked_sub(bits(1)), None);
+        assert_eq!(min.checked_neg(), None);
+    }
+
+    #[test]
+    fn saturating_clamps_both_ends() {
+        let (max, min) = (Fixed::max_value(), Fixed::min_value());
+        assert_eq!(max.saturating_add(bits(1)), max);
+        assert_eq!(min.saturating_add(bits(-1)), min);
+        assert_eq!(min.saturating_sub(bits(1)), min);
+        assert_eq!(max.saturating_sub(bits(-1)), max);
+        assert_eq!(min.saturating_neg(), max);
+
+        assert_eq!(max.saturating_mul(2.into()), max);
+        assert_eq!(max.saturating_mul((-2).into()), min);
+        assert_eq!(min.saturating_mul(2.into()), min);
+        assert_eq!(min.saturating_mul((-1).into()), max);
+
+        assert_eq!(max.saturating_div(fixed!(0.5)), max);
+        assert_eq!(min.saturating_div(fixed!(0.5)), min);
+        assert_eq!(min.saturating_div((-1).into()), max);
+        assert_eq!(Fixed::from(1).saturating_div(0.into()), max);
+        assert_eq!(Fixed::from(-1).saturating_div(0.into()), min);
+        assert_eq!(Fixed::from(0).saturating_div(0.into()), max);
+
+        // Values that fit are left alone
+        assert_eq!(Fixed::from(3).saturating_mul((-2).into()), Fixed::from(-6));
+        assert_eq!(Fixed::from(3).saturating_div(2.into()), fixed!(1.5));
+    }
+
+    #[test]
+    fn wrapping_wraps_around() {
+        let (max, min) = (Fixed::max_value(), Fixed::min_value());
+        assert_eq!(max.wrapping_add(bits(1)), min);
+        assert_eq!(min.wrapping_sub(bits(1)), max);
+        assert_eq!(min.wrapping_neg(), min);
+        // 0x7fffffff * 2 = 0xfffffffe, which is -2 as an i32
+        assert_eq!(max.wrapping_mul(2.into()), bits(-2));
+        assert_eq!(min.wrapping_mul((-1).into()), min);
+        assert_eq!(min.wrapping_div((-1).into()), min);
+        assert_eq!(max.wrapping_div(fixed!(0.5)), bits(-2));
+    }
+
+    #[test]
+    #[should_panic]
+    fn wrapping_div_by_zero_panics() {
+        Fixed::from(1).wrapping_div(0.into());
+    }
+
+    #[test]
+    fn wide_mul_past_128() {
+        // The bits of these multiply to more than an i32 holds, so this only works because the
+        // product is widened first
+        assert_eq!(Fixed::from(200) * Fixed::from(300), bits(60000 << 8));
+        assert_eq!(Fixed::from(200).checked_mul(300.into()), Some(bits(60000 << 8)));
+        assert_eq!(Fixed::from(-200).checked_mul(300.into()), Some(bits(-60000 << 8)));
+        assert_eq!(Fixed::from(-200).checked_mul((-300).into()), Some(bits(60000 << 8)));
+        assert_eq!(fixed!(129.5).checked_mul(fixed!(-129.5)), Some(fixed!(-16770.25)));
+        assert_eq!(Fixed::from(20000).checked_mul(20000.into()), None);
+
+        // Extra precision is floored away, towards negative infinity
+        assert_eq!(fixed!(0.5) * fixed!(0.5), fixed!(0.25));
+        assert_eq!(bits(1) * bits(1), bits(0));
+        assert_eq!(bits(-1) * bits(1), bits(-1));
+
+        // Same for the 8.8 type, where ±128 is the whole range
+        assert_eq!(I8F8::from(100).checked_mul(fixed!(I8F8: 0.5)), Some(I8F8::from(50)));
+        assert_eq!(I8F8::from(100).checked_mul(2.into()), None);
+        assert_eq!(I8F8::from(-64).checked_mul(2.into()), Some(I8F8::min_value()));
+    }
+
+    #[test]
+    fn sqrt_edges() {
+        assert_eq!(Fixed::from(0).sqrt(), Fixed::from(0));
+        // sqrt(1/256) is 1/16
+        assert_eq!(bits(1).sqrt(), bits(16));
+        assert_eq!(Fixed::from(4).sqrt(), Fixed::from(2));
+        // Too big to shift before the BIOS call, so this loses the bottom few bits
+        assert_eq!(Fixed::max_value().sqrt(), bits(46340 << 4));
+
+        assert_eq!(bits(-1).checked_sqrt(), None);
+        assert_eq!(Fixed::min_value().checked_sqrt(), None);
+    }
+
+    #[test]
+    fn rounding() {
+        // Negative, halfway, and just either side of halfway
+        assert_eq!(bits(-384).floor(), Fixed::from(-2));
+        assert_eq!(bits(-384).ceil(), Fixed::from(-1));
+        assert_eq!(bits(-384).trunc(), Fixed::from(-1));
+        assert_eq!(bits(-384).round(), Fixed::from(-2));
+        assert_eq!(bits(-383).round(), Fixed::from(-1));
+        assert_eq!(bits(-385).round(), Fixed::from(-2));
+        assert_eq!(bits(384).round(), Fixed::from(2));
+        assert_eq!(bits(383).round(), Fixed::from(1));
+
+        // Between -1 and 0
+        assert_eq!(bits(-1).floor(), Fixed::from(-1));
+        assert_eq!(bits(-1).ceil(), Fixed::from(0));
+        assert_eq!(bits(-1).trunc(), Fixed::from(0));
+        assert_eq!(bits(-1).round(), Fixed::from(0));
+        assert_eq!(bits(-128).round(), Fixed::from(-1));
+        assert_eq!(bits(-127).round(), Fixed::from(0));
+
+        // Whole numbers stay put
+        for &n in &[-3, -1, 0, 1, 3] {
+            let whole = Fixed::from(n);
+            assert_eq!((whole.floor(), whole.ceil(), whole.trunc(), whole.round()), (whole, whole, whole, whole));
+        }
+
+        assert_eq!(bits(-1).to_int_floor(), -1);
+        assert_eq!(bits(-1).to_int_ceil(), 0);
+        assert_eq!(bits(-1).to_int_trunc(), 0);
+        assert_eq!(bits(-384).to_int_round(), -2);
+        assert_eq!(bits(-383).to_int_round(), -1);
+        assert_eq!(bits(383).to_int_round(), 1);
+        assert_eq!(bits(384).to_int_round(), 2);
+        assert_eq!(bits(-640).to_int_trunc(), -2);
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(format!("{}", bits(-384)), "-1.5");
+        assert_eq!(format!("{:?}", bits(-384)), "-1.5");
+        assert_eq!(format!("{}", bits(-1)), "-0.00390625");
+        assert_eq!(format!("{:?}", bits(-128)), "-0.5");
+        assert_eq!(format!("{}", Fixed::from(0)), "0");
+        assert_eq!(format!("{}", Fixed::min_value()), "-8388608");
+        assert_eq!(format!("{}", Fixed::max_value()), "8388607.99609375");
+        assert_eq!(format!("{}", I16F16::from_bits(-98304)), "-1.5");
+
+        // With a precision, rounding half away from zero
+        assert_eq!(format!("{:.2}", bits(-383)), "-1.50");
+        assert_eq!(format!("{:.1}", bits(-448)), "-1.8");
+        assert_eq!(format!("{:.0}", bits(-384)), "-2");
+        assert_eq!(format!("{:.0}", bits(384)), "2");
+        assert_eq!(format!("{:.3}", bits(-32)), "-0.125");
+        assert_eq!(format!("{:.1}", bits(-32)), "-0.1");
+        assert_eq!(format!("{:?}", bits(-32)), "-0.125");
+        // Tiny negatives that round to zero don't keep their sign
+        assert_eq!(format!("{:.2}", bits(-1)), "0.00");
+        assert_eq!(format!("{:.0}", bits(-100)), "0");
+        // More places than are exact get padded
+        assert_eq!(format!("{:.12}", bits(1)), "0.003906250000");
+        // Width and alignment still work, sign and all
+        assert_eq!(format!("{:>8.1}", bits(-640)), "    -2.5");
+        assert_eq!(format!("{:+}", bits(640)), "+2.5");
+    }
+
+    #[test]
+    fn parsing() {
+        assert_eq!("0.213".parse::<Fixed>(), Ok(bits(55)));
+        assert_eq!("-1.5".parse::<Fixed>(), Ok(bits(-384)));
+        assert_eq!("-0.5".parse::<Fixed>(), Ok(bits(-128)));
+        assert_eq!("+12".parse::<Fixed>(), Ok(Fixed::from(12)));
+        assert_eq!(".5".parse::<Fixed>(), Ok(bits(128)));
+        assert_eq!("5.".parse::<Fixed>(), Ok(Fixed::from(5)));
+        assert_eq!("1_000.25".parse::<Fixed>(), Ok(bits(256064)));
+        // Rounds to the nearest step, and away from zero on a tie, carrying into the whole part
+        assert_eq!("0.999".parse::<Fixed>(), Ok(Fixed::from(1)));
+        assert_eq!("-0.001953125".parse::<Fixed>(), Ok(bits(-1)));
+        assert_eq!("0.0019".parse::<Fixed>(), Ok(bits(0)));
+        assert_eq!("-0.0019".parse::<Fixed>(), Ok(bits(0)));
+        // Digits past the ninth place are ignored
+        assert_eq!("0.5000000009".parse::<Fixed>(), Ok(bits(128)));
+
+        assert_eq!("".parse::<Fixed>(), Err(ParseFixedError::Empty));
+        assert_eq!("-".parse::<Fixed>(), Err(ParseFixedError::Empty));
+        assert_eq!(".".parse::<Fixed>(), Err(ParseFixedError::Empty));
+        assert_eq!("1.2.3".parse::<Fixed>(), Err(ParseFixedError::InvalidDigit));
+        assert_eq!("1e3".parse::<Fixed>(), Err(ParseFixedError::InvalidDigit));
+        assert_eq!(" 1".parse::<Fixed>(), Err(ParseFixedError::InvalidDigit));
+        assert_eq!("--1".parse::<Fixed>(), Err(ParseFixedError::InvalidDigit));
+        assert_eq!("8388608".parse::<Fixed>(), Err(ParseFixedError::Overflow));
+        assert_eq!("-8388608".parse::<Fixed>(), Ok(Fixed::min_value()));
+        assert_eq!("99999999999999999999".parse::<Fixed>(), Err(ParseFixedError::Overflow));
+        assert_eq!("128".parse::<I8F8>(), Err(ParseFixedError::Overflow));
+        assert_eq!("-128".parse::<I8F8>(), Ok(I8F8::min_value()));
+    }
+
+    #[test]
+    fn format_parse_round_trip() {
+        let mut n: i32 = -0x7fff_ffff;
+        while n < 0x7fff_0000 {
+            let value = bits(n);
+            assert_eq!(format!("{}", value).parse::<Fixed>(), Ok(value));
+            n += 0x1234_567;
+        }
+        for n in -1000 .. 1000 {
+            let value = bits(n);
+            assert_eq!(format!("{}", value).parse::<Fixed>(), Ok(value));
+            let value = I16F16::from_bits(n * 65);
+            assert_eq!(format!("{:?}", value).parse::<I16F16>(), Ok(value));
+        }
+    }
+
+    #[test]
+    #[cfg_attr(debug_assertions, should_panic)]
+    fn sqrt_of_negative() {
+        assert_eq!(bits(-1).sqrt(), bits(0));
+    }
+}
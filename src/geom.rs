@@ -1,21 +1,35 @@
 use euclid::{TypedPoint2D, TypedRect, TypedSize2D, TypedVector2D};
 use num_traits::clamp;
 
-use crate::fixed::Fixed;
-//use fpa::I24F8;
+use crate::angle::Angle;
+use crate::fixed::I24F8;
 
 pub type ScreenPixel = u16;
-pub type WorldUnit = Fixed;
+pub type WorldUnit = I24F8;
 pub type WorldStorage = i32;
 pub type WorldWhole = i16;
 pub struct ScreenSpace;
 pub struct WorldSpace;
+/// Coordinates of 16×16 metatiles, which are what places are made of and what collision uses.
+pub struct TileSpace;
+/// Coordinates of 8×8 chars, which are what the hardware actually draws.  Each tile is 2×2 chars.
+pub struct CharSpace;
 
 pub type Point = TypedPoint2D<WorldUnit, WorldSpace>;
 pub type Rect = TypedRect<WorldUnit, WorldSpace>;
 pub type Size = TypedSize2D<WorldUnit, WorldSpace>;
 pub type Vector = TypedVector2D<WorldUnit, WorldSpace>;
 
+pub type TileCoord = i32;
+pub type TilePoint = TypedPoint2D<TileCoord, TileSpace>;
+pub type TileRect = TypedRect<TileCoord, TileSpace>;
+pub type CharPoint = TypedPoint2D<TileCoord, CharSpace>;
+
+pub const CHAR_SIZE_BITS: usize = 3;
+pub const TILE_SIZE_BITS: usize = 4;
+pub const CHAR_SIZE: WorldWhole = 1 << CHAR_SIZE_BITS;
+pub const TILE_SIZE: WorldWhole = 1 << TILE_SIZE_BITS;
+
 pub trait RectExt {
     fn touches(&self, other: &Self) -> bool;
 }
@@ -39,6 +53,9 @@ pub trait VectorExt<T> {
     // Local additions
     fn perpendicular(self) -> Self;
     fn project_on(self, axis: Self) -> Self;
+    fn from_angle(angle: Angle, length: T) -> Self;
+    fn angle(&self) -> Angle;
+    fn rotate(self, angle: Angle) -> Self;
 }
 
 impl VectorExt<WorldUnit> for Vector {
@@ -57,6 +74,20 @@ impl VectorExt<WorldUnit> for Vector {
     fn project_on(self, axis: Self) -> Self {
         axis * self.dot(axis) / axis.square_length()
     }
+
+    fn from_angle(angle: Angle, length: WorldUnit) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::new(cos * length, sin * length)
+    }
+
+    fn angle(&self) -> Angle {
+        Angle::atan2(self.y, self.x)
+    }
+
+    fn rotate(self, angle: Angle) -> Self {
+        let (sin, cos) = angle.sin_cos();
+        Self::new(self.x * cos - self.y * sin, self.x * sin + self.y * cos)
+    }
 }
 
 // Versions of the euclid helper functions that also perform fixed conversion
@@ -73,6 +104,75 @@ pub fn vec2(x: WorldWhole, y: WorldWhole) -> Vector {
     euclid::vec2(x.into(), y.into())
 }
 
+// Conversions between world, tile, and char space.  These all floor, so negative coordinates land
+// in the tile to their upper left, same as positive ones.
+
+/// Which tile a world coordinate falls in.
+pub fn world_to_tile_coord(n: WorldUnit) -> TileCoord {
+    n.to_bits() >> (WorldUnit::FRACTIONAL_BITS + TILE_SIZE_BITS)
+}
+
+/// Which tile a world point falls in.
+pub fn world_to_tile(point: Point) -> TilePoint {
+    TilePoint::new(world_to_tile_coord(point.x), world_to_tile_coord(point.y))
+}
+
+/// The world-space area covered by a tile.
+pub fn tile_to_world_rect(tile: TilePoint) -> Rect {
+    let shift = WorldUnit::FRACTIONAL_BITS + TILE_SIZE_BITS;
+    euclid::rect(
+        WorldUnit::from_bits(tile.x << shift), WorldUnit::from_bits(tile.y << shift),
+        TILE_SIZE.into(), TILE_SIZE.into())
+}
+
+/// The world-space area covered by a block of tiles.
+pub fn tiles_to_world_rect(tiles: TileRect) -> Rect {
+    let shift = WorldUnit::FRACTIONAL_BITS + TILE_SIZE_BITS;
+    euclid::rect(
+        WorldUnit::from_bits(tiles.origin.x << shift), WorldUnit::from_bits(tiles.origin.y << shift),
+        WorldUnit::from_bits(tiles.size.width << shift), WorldUnit::from_bits(tiles.size.height << shift))
+}
+
+/// All the tiles a world-space rect touches, including ones it only shares an edge with.
+pub fn tiles_touching(rect: Rect) -> TileRect {
+    let min = world_to_tile(rect.origin);
+    let max = world_to_tile(rect.bottom_right());
+    euclid::rect(min.x, min.y, max.x - min.x + 1, max.y - min.y + 1)
+}
+
+/// Which char a world point falls in.
+pub fn world_to_char(point: Point) -> CharPoint {
+    let shift = WorldUnit::FRACTIONAL_BITS + CHAR_SIZE_BITS;
+    CharPoint::new(point.x.to_bits() >> shift, point.y.to_bits() >> shift)
+}
+
+/// The world-space area covered by a char.
+pub fn char_to_world_rect(ch: CharPoint) -> Rect {
+    let shift = WorldUnit::FRACTIONAL_BITS + CHAR_SIZE_BITS;
+    euclid::rect(
+        WorldUnit::from_bits(ch.x << shift), WorldUnit::from_bits(ch.y << shift),
+        CHAR_SIZE.into(), CHAR_SIZE.into())
+}
+
+/// The top-left char of a tile.
+pub fn tile_to_char(tile: TilePoint) -> CharPoint {
+    let shift = TILE_SIZE_BITS - CHAR_SIZE_BITS;
+    CharPoint::new(tile.x << shift, tile.y << shift)
+}
+
+/// Which tile a char belongs to.
+pub fn char_to_tile(ch: CharPoint) -> TilePoint {
+    let shift = TILE_SIZE_BITS - CHAR_SIZE_BITS;
+    TilePoint::new(ch.x >> shift, ch.y >> shift)
+}
+
+/// Where a char sits within its tile, as an index into `Tile::chars`, which is row-major.
+pub fn char_index_in_tile(ch: CharPoint) -> usize {
+    let shift = TILE_SIZE_BITS - CHAR_SIZE_BITS;
+    let mask = (1 << shift) - 1;
+    (((ch.y & mask) << shift) | (ch.x & mask)) as usize
+}
+
 #[derive(Clone, Copy)]
 pub enum Bounds {
     Empty,
@@ -169,3 +269,33 @@ function Camera:draw()
         self.height * (1 - 2 * self.margin))
 end
 */
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn conversions_floor_negatives() {
+        assert_eq!(world_to_tile(point2(15, 16)), TilePoint::new(0, 1));
+        assert_eq!(world_to_tile(Point::new(WorldUnit::from_bits(-1), 0.into())), TilePoint::new(-1, 0));
+        assert_eq!(world_to_char(point2(-1, -8)), CharPoint::new(-1, -1));
+        assert_eq!(world_to_char(point2(-9, 7)), CharPoint::new(-2, 0));
+        assert_eq!(char_to_tile(CharPoint::new(-1, 3)), TilePoint::new(-1, 1));
+        assert_eq!(char_index_in_tile(CharPoint::new(-1, 3)), 3);
+    }
+
+    #[test]
+    fn round_trips() {
+        for &(x, y) in &[(0, 0), (3, -2), (-5, 7)] {
+            let tile = TilePoint::new(x, y);
+            let ch = tile_to_char(tile);
+            assert_eq!(char_to_tile(ch), tile);
+            assert_eq!(char_index_in_tile(ch), 0);
+            assert_eq!(char_to_world_rect(ch).origin, tile_to_world_rect(tile).origin);
+            assert_eq!(world_to_char(char_to_world_rect(ch).origin), ch);
+            assert_eq!(world_to_tile(tile_to_world_rect(tile).origin), tile);
+        }
+        assert_eq!(char_to_world_rect(CharPoint::new(-1, 2)), rect(-8, 16, 8, 8));
+    }
+}
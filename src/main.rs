@@ -1,4 +1,4 @@
-#![no_std]
+#![cfg_attr(not(test), no_std)]
 #![feature(start)]
 
 extern crate arrayvec;
@@ -6,13 +6,18 @@ extern crate euclid;
 extern crate gba;
 extern crate num_traits;
 
-mod data;
+// These come first so their macros are visible to everything else
 mod debug;
 mod fixed;
+
+mod angle;
+mod data;
 mod geom;
+mod sprite;
 mod whammo;
 
 
+#[cfg(not(test))]
 #[panic_handler]
 fn panic(panic_info: &core::panic::PanicInfo) -> ! {
     use gba::mgba::{MGBADebug, MGBADebugLevel};
@@ -27,6 +32,7 @@ fn panic(panic_info: &core::panic::PanicInfo) -> ! {
 
 
 
+use arrayvec::ArrayVec;
 use gba::{
     base::volatile::VolAddress,
     io::{
@@ -42,12 +48,46 @@ use gba::{
 };
 
 
+use crate::angle::Angle;
 use crate::data::PALETTE;
-use crate::data::places::TEST_PLACE;
+use crate::data::places::{Place, TEST_PLACE, TriggerKind};
 use crate::fixed::Fixed;
-use crate::geom::{Camera, Point, Rect, Vector, VectorExt, point2, rect, size2, vec2};
-use crate::whammo::shapes::{Contact, Polygon};
-use crate::whammo::CollisionVec;
+use crate::geom::{Camera, CharPoint, Point, Rect, Vector, VectorExt, char_index_in_tile, char_to_tile, point2, rect, size2, tiles_touching, vec2, world_to_char};
+use crate::sprite::{AffineAllocator, AffineMatrix, AffineSlot, ATTR0_AFFINE, ATTR0_DOUBLE_SIZE};
+use crate::whammo::shapes::{Polygon, Shape, flip_rect_x};
+use crate::whammo::{CollisionResult, Crush};
+use crate::whammo::blockmap::{Blockmap, ShapeId};
+use crate::whammo::layers::{CollisionFilter, Layers};
+use crate::whammo::slide;
+use crate::whammo::query::{CONTACT_TOLERANCE, Depenetration, Surface, contacts, depenetration, overlapping};
+use crate::whammo::materials::Material;
+use crate::whammo::tilemap::TileColliders;
+use crate::whammo::triggers::{TriggerPhase, Triggers};
+
+// Movement tunables
+/// Added to Lexy's vertical velocity every frame.  (Formerly 16/75, which is the same value.)
+const GRAVITY: Fixed = fixed!(0.21);
+const WALK_SPEED: Fixed = fixed!(1.5);
+const JUMP_SPEED: Fixed = fixed!(4);
+/// How quickly she speeds up and slows down on ice, per frame.
+const ICE_ACCELERATION: Fixed = fixed!(0.0625);
+/// Landing slower than this doesn't bounce, or she'd never stop hopping.
+const MIN_BOUNCE_SPEED: Fixed = fixed!(1);
+/// Landing at least this fast squashes her sprite for a moment.
+const LANDING_SQUASH_SPEED: Fixed = fixed!(3);
+const SQUASH_FRAMES: u8 = 6;
+const SQUASH_SCALE_X: Fixed = fixed!(1.25);
+const SQUASH_SCALE_Y: Fixed = fixed!(0.75);
+/// Fastest she gets pushed out of something she's stuck inside, per frame.  Slow enough that it
+/// doesn't look like a teleport.
+const DEPENETRATION_SPEED: Fixed = fixed!(2);
+
+/// Width and height of a text screenblock, in chars.
+const SCREENBLOCK_CHARS: i32 = 32;
+/// What props are drawn with, for now.  Same as the middle of the ground.
+const PROP_CHAR: u16 = 7;
+/// Most props a place can have.
+const MAX_PROPS: usize = 16;
 
 #[start]
 fn main(_argc: isize, _argv: *const *const u8) -> isize {
@@ -93,32 +133,31 @@ fn main(_argc: isize, _argv: *const *const u8) -> isize {
         );
     }
 
-    let mut p = unsafe { SCREEN_BASE_BLOCKS.index(8).cast::<TextScreenblockEntry>() };
-    for row in &place.tiles[..16] {
-        for &tid in &row[..16] {
-            let tile = &place.tileset.tiles[tid as usize];
-            let tse = TextScreenblockEntry::from_tile_id(tile.chars[0] as u16);
-            unsafe {
-                p.write(tse);
-                p = p.offset(1);
-            }
-            let tse = TextScreenblockEntry::from_tile_id(tile.chars[1] as u16);
+    // Fill the screenblock, which is 32×32 chars, from the top left of the place
+    let screenblock = unsafe { SCREEN_BASE_BLOCKS.index(8).cast::<TextScreenblockEntry>() };
+    for cy in 0 .. SCREENBLOCK_CHARS {
+        for cx in 0 .. SCREENBLOCK_CHARS {
+            let ch = CharPoint::new(cx, cy);
+            let tile_point = char_to_tile(ch);
+            let tile = place.tile_at(tile_point);
+            let tse = TextScreenblockEntry::from_tile_id(tile.chars[char_index_in_tile(ch)] as u16);
             unsafe {
-                p.write(tse);
-                p = p.offset(1);
+                screenblock.offset((cy * SCREENBLOCK_CHARS + cx) as isize).write(tse);
             }
         }
-        for &tid in &row[..16] {
-            let tile = &place.tileset.tiles[tid as usize];
-            let tse = TextScreenblockEntry::from_tile_id(tile.chars[2] as u16);
-            unsafe {
-                p.write(tse);
-                p = p.offset(1);
-            }
-            let tse = TextScreenblockEntry::from_tile_id(tile.chars[3] as u16);
-            unsafe {
-                p.write(tse);
-                p = p.offset(1);
+    }
+
+    // Props aren't part of the tilemap, so they go on bg1, in front of it
+    let prop_screenblock = unsafe { SCREEN_BASE_BLOCKS.index(16).cast::<TextScreenblockEntry>() };
+    for prop in place.props {
+        let area = prop.area();
+        let top_left = world_to_char(area.origin);
+        let bottom_right = world_to_char(area.bottom_right());
+        for cy in top_left.y.max(0) .. bottom_right.y.min(SCREENBLOCK_CHARS) {
+            for cx in top_left.x.max(0) .. bottom_right.x.min(SCREENBLOCK_CHARS) {
+                unsafe {
+                    prop_screenblock.offset((cy * SCREENBLOCK_CHARS + cx) as isize).write(TextScreenblockEntry::from_tile_id(PROP_CHAR));
+                }
             }
         }
     }
@@ -129,24 +168,11 @@ fn main(_argc: isize, _argv: *const *const u8) -> isize {
     // Display Control
     DISPCNT.write(DisplayControlSetting::new().with_bg0(true).with_bg1(true).with_obj(true).with_oam_memory_1d(true));
 
-    let mut game = Game{ camera: Camera::new() };
+    let mut game = Game::new(place);
     game.camera.bounds = crate::geom::Bounds::BBox(rect(0, 0, 1024, 1024));
     game.camera.size = size2(240, 160);
     game.camera.margin = size2(64, 32);
-    let mut lexy = {
-        let position = point2(48, 80);
-        let bbox = rect(-6, -26, 12, 27);
-        Lexy{
-            position,
-            velocity: vec2(0, 0),
-            anchor: point2(17, 47),
-            bbox,
-            shape: Polygon::from_rect(bbox.translate(&position.to_vector())),
-            facing_left: false,
-            sprite_index: 0,
-            sprite_timer: 0,
-        }
-    };
+    let mut lexy = Lexy::new(&mut game, place.spawn_point());
 
     let timer_disabled = TimerControlSetting::new().with_tick_rate(TimerTickRate::CPU64);
     let timer_enabled = timer_disabled.with_enabled(true);
@@ -160,8 +186,41 @@ fn main(_argc: isize, _argv: *const *const u8) -> isize {
         TM0CNT_H.write(timer_disabled);
         TM0CNT_H.write(timer_enabled);
 
+        // Pick up any tile changes from last frame before anything moves
+        if game.tile_colliders.refresh(game.place) {
+            lexy.maybe_stuck = true;
+        }
+        if lexy.maybe_stuck {
+            lexy.depenetrate(&mut game);
+        }
+
         step(&mut game, &mut lexy);
-        lexy.update(&game);
+        lexy.update(&mut game);
+
+        // See what she's wandered into
+        let mut checkpoint = None;
+        let mut hurt = false;
+        game.triggers.update(
+            &game.actors,
+            |id| if Some(id) == lexy.blockmap_id { Some(&lexy.shape) } else { None },
+            |event| {
+                // Nothing else can set these off yet, but nothing else should, either
+                if Some(event.actor) != lexy.blockmap_id || event.phase != TriggerPhase::Enter {
+                    return;
+                }
+                spew!("entered trigger {:?}: {:?}", event.trigger, event.data);
+                match event.data {
+                    TriggerKind::Checkpoint(x, y) => checkpoint = Some(point2(x, y)),
+                    TriggerKind::Hazard => hurt = true,
+                }
+            },
+        );
+        if let Some(point) = checkpoint {
+            lexy.checkpoint = point;
+        }
+        if hurt {
+            lexy.respawn(&mut game, lexy.checkpoint);
+        }
 
         // UPDATE CAMERA
         // TODO maybe aim at lexy's eyes or something, atm she can get closer to the top of the
@@ -199,13 +258,70 @@ fn update_lexy_sprite(sprite_index: usize) {
 }
 
 struct Game {
+    place: &'static Place,
     camera: Camera,
+    affine_matrices: AffineAllocator,
+    tile_colliders: TileColliders,
+    /// Broadphase for everything that moves
+    actors: Blockmap,
+    triggers: Triggers<TriggerKind>,
+    props: ArrayVec<[Prop; MAX_PROPS]>,
+}
+
+impl Game {
+    /// Set up a place, with its triggers and props.  Lexy comes separately.
+    fn new(place: &'static Place) -> Self {
+        let mut game = Game{
+            place,
+            camera: Camera::new(),
+            affine_matrices: AffineAllocator::new(),
+            tile_colliders: TileColliders::new(),
+            actors: Blockmap::new(),
+            triggers: Triggers::new(),
+            props: ArrayVec::new(),
+        };
+        for trigger in place.triggers {
+            let shape = Shape::Polygon(Polygon::from_rect(trigger.area()));
+            if game.triggers.add(shape, Layers::PLAYER, trigger.kind).is_none() {
+                spew!("too many triggers; dropped {:?}", trigger);
+            }
+        }
+        for prop in place.props {
+            let shape = Shape::Polygon(Polygon::from_rect(prop.area()));
+            let blockmap_id = match game.actors.add(shape.bbox(), CollisionFilter::TERRAIN) {
+                Some(id) => id,
+                None => {
+                    spew!("blockmap full; dropped {:?}", prop);
+                    continue;
+                }
+            };
+            game.actors.set_material(blockmap_id, prop.material);
+            if game.props.try_push(Prop{ shape, blockmap_id }).is_err() {
+                spew!("too many props; dropped {:?}", prop);
+                game.actors.remove(blockmap_id);
+            }
+        }
+        game
+    }
+
+    /// Look up the shape of something in `actors`.  Lexy isn't in here, since she's always the
+    /// one asking.
+    fn shape_of(&self, id: ShapeId) -> Option<&Shape> {
+        self.props.iter().find(|prop| prop.blockmap_id == id).map(|prop| &prop.shape)
+    }
+}
+
+/// Something solid that isn't part of the tilemap.  Just sits there, for now.
+struct Prop {
+    shape: Shape,
+    blockmap_id: ShapeId,
 }
 
 trait Entity {
-    fn update(&mut self, game: &Game);
-    fn nudge(&mut self, displacement: Vector) -> Vector;
-    fn collider_sweep(&self, shape: &Polygon, attempted: Vector, hits: &mut CollisionVec /*, pass_callback */) -> Vector;
+    fn update(&mut self, game: &mut Game);
+    fn nudge(&mut self, game: &mut Game, displacement: Vector) -> Vector;
+    /// Called when `nudge` gets stuck between two surfaces facing each other.
+    fn crushed(&mut self, game: &mut Game, crush: Crush);
 }
 
 struct Lexy {
@@ -213,142 +329,184 @@ struct Lexy {
     velocity: Vector,
     anchor: Point,
     bbox: Rect,
-    shape: Polygon,
+    shape: Shape,
     facing_left: bool,
+    /// Affine matrix to draw with, if any.  Whoever sets this is responsible for writing the
+    /// matrix, including mirroring it when she faces left.
+    affine: Option<AffineSlot>,
+    /// Frames left of being squashed from a hard landing
+    squash_timer: u8,
+    blockmap_id: Option<ShapeId>,
+    /// Where to respawn
+    checkpoint: Point,
+    /// Set when she might have ended up inside something, and cleared once she's been pushed
+    /// back out
+    maybe_stuck: bool,
+    filter: CollisionFilter,
     sprite_index: usize,
     sprite_timer: usize,
 }
 
 
-fn _is_vector_almost_zero(vec: Vector) -> bool {
-    vec.x.abs() * 64 < 1 && vec.y.abs() * 64 < 1
-}
-
-
-enum SlideResult {
-    Stuck,
-    Slid(Vector),
-}
-fn slide_along_normals(hits: &CollisionVec, direction: Vector) -> SlideResult {
-    let perp = direction.perpendicular();
-    let mut minleftdot;
-    let mut minleftnorm;
-    let mut minrightdot;
-    let mut minrightnorm;
-    let mut right_possible = true;
-    let mut left_possible = true;
-
-    let mut iter = hits.iter();
-    if let Some(collision) = iter.next() {
-        minleftdot = collision.left_normal_dot;
-        minleftnorm = collision.left_normal;
-        minrightdot = collision.right_normal_dot;
-        minrightnorm = collision.right_normal;
+/// Everything a shape would run into moving by `attempted`, in no particular order; that gets
+/// sorted out by `slide::nudge`.  `filter` is what's moving, `layers` is what gets in its way, and
+/// `ignore` is usually itself.
+fn collider_sweep(game: &Game, filter: CollisionFilter, layers: Layers, ignore: Option<ShapeId>, shape: &Shape, attempted: Vector /*, pass_callback */) -> CollisionResult {
+    let mut result = CollisionResult::new(attempted);
+    let xbbox = shape.extended_bbox(attempted);
+    // Check out the tilemap
+    if filter.interacts_with(CollisionFilter::TERRAIN) {
+        let tiles = tiles_touching(xbbox);
+        game.tile_colliders.each_touching(game.place, tiles, |collider| {
+            if let Some(hit) = shape.slide_towards_box(collider, attempted) {
+                result.add(hit);
+            }
+        });
     }
-    else {
-        // No hits at all (which doesn't make much sense), so we're free to move wherever
-        return SlideResult::Slid(direction);
-    }
-
-    // So, here's the problem.  At first blush, this seems easy enough: just
-    // pick the normal that restricts us the most, which is the one that faces
-    // most towards us (i.e. has the most negative dot product), and slide
-    // along that.  Alas, there are two major problems there.
-    // 1. We might be blocked on /both sides/ and thus can't move at all.  To
-    // detect this, we have to sort normals into "left" and "right", find the
-    // worst normal on each side, and then reconcile at the end.
-    // 2. Each hit might be a corner collision and have multiple normals.
-    // While hitting more objects and thus encountering more normals will
-    // /reduce/ our available slide area, hitting a corner /increases/ it.  So
-    // within a single hit, we have to do the same thing in reverse, finding
-    // the BEST normal on each side and counting that one.
-    // FIXME there are also two problems with the data we get out of whammo
-    // atm: (a) a corner collision might produce more than two normals which
-    // feels ambiguous (but maybe it isn't; remember those normals are from
-    // both us and the thing we hit?  maybe draw a diagram to check on this),
-    // and (b) MultiShape blindly crams all the normals into a single table,
-    // even though normals from different shapes combine differently.  for the
-    // latter problem, maybe we should just return a left_normal and
-    // right_normal in each hit?  i mean we do the dot products in whammo
-    // itself already, so that'd save us a lot of effort.  only drawback i can
-    // think of is that moving by zero would make all those normals kind of
-    // meaningless, but i think we could just look at the overall direction of
-    // contact...?  whatever that means?
-    for collision in iter {
-        if collision.touchtype == Contact::Overlap /* || collision.passable */ {
+    // And anything else solid nearby
+    for id in game.actors.neighbors_in(&xbbox, layers).iter() {
+        if Some(id) == ignore {
             continue;
         }
-
-        // TODO comment stuff in shapes.lua
-        // TODO update comments here, delete dead code
-        // TODO explain why i used <= below (oh no i don't remember, but i think it was related to how this is done against the last slide only)
-        // FIXME i'm now using normals compared against our /last slide/ on our /velocity/ and it's unclear what ramifications that could have (especially since it already had enough ramifications to need the <=) -- think about this i guess lol
-
-        if left_possible && collision.left_normal.is_some() {
-            if collision.left_normal_dot <= minleftdot {
-                minleftdot = collision.left_normal_dot;
-                minleftnorm = collision.left_normal;
-            }
-        }
-        else {
-            left_possible = false;
-            minleftnorm = None;
-        }
-
-        if right_possible && collision.right_normal.is_some() {
-            if collision.right_normal_dot <= minrightdot {
-                minrightdot = collision.right_normal_dot;
-                minrightnorm = collision.right_normal;
-            }
+        if ! game.actors.filter(id).map_or(false, |other| filter.interacts_with(other)) {
+            continue;
         }
-        else {
-            right_possible = false;
-            minrightnorm = None;
+        if let Some(hit) = game.shape_of(id).and_then(|other| shape.slide_towards(other, attempted)) {
+            result.add(hit);
         }
     }
+    result
+}
+
 
-    if ! left_possible && ! right_possible {
-        return SlideResult::Stuck;
+impl Lexy {
+    /// Put her in the game at the given position.
+    fn new(game: &mut Game, position: Point) -> Self {
+        let bbox = rect(-6, -26, 12, 27);
+        let mut lexy = Lexy{
+            position,
+            velocity: vec2(0, 0),
+            anchor: point2(17, 47),
+            bbox,
+            shape: Shape::Polygon(Polygon::from_rect(bbox.translate(&position.to_vector()))),
+            facing_left: false,
+            affine: game.affine_matrices.allocate(),
+            squash_timer: 0,
+            blockmap_id: None,
+            checkpoint: position,
+            maybe_stuck: true,
+            // She runs into walls and enemies, and picks things up, but doesn't get hit by her
+            // own shots
+            filter: CollisionFilter::new(Layers::PLAYER, Layers::TERRAIN | Layers::ENEMY | Layers::PICKUP),
+            sprite_index: 0,
+            sprite_timer: 0,
+        };
+        lexy.blockmap_id = game.actors.add(lexy.shape.bbox(), lexy.filter);
+        lexy
     }
 
-    let axis;
-    if ! left_possible {
-        axis = minrightnorm;
+    /// The layers that actually get in her way.  Enemies hurt and pickups get picked up, but
+    /// neither one stops her moving.
+    fn solid_layers(&self) -> Layers {
+        self.filter.mask & Layers::TERRAIN
     }
-    else if ! right_possible {
-        axis = minleftnorm;
+
+    /// Move her by some amount, without regard for what's in the way.  Anything that moves her
+    /// should go through here, so the broadphase never loses track of her.
+    fn move_by(&mut self, game: &mut Game, delta: Vector) {
+        self.shape.move_by(delta);
+        self.position += delta;
+        self.update_blockmap(game);
     }
-    else if minleftdot > minrightdot {
-        axis = minleftnorm;
+
+    /// Key the broadphase on where she might get to next frame.  Needs doing whenever her shape
+    /// changes.
+    fn update_blockmap(&self, game: &mut Game) {
+        if let Some(id) = self.blockmap_id {
+            game.actors.update(id, self.shape.extended_bbox(self.velocity));
+        }
     }
-    else {
-        axis = minrightnorm;
+
+    /// Put her back at the given position, at rest, as though nothing happened.
+    fn respawn(&mut self, game: &mut Game, position: Point) {
+        self.velocity = Vector::zero();
+        self.maybe_stuck = true;
+        self.move_by(game, position - self.position);
     }
-    // FIXME this makes me realize that this function doesn't use direction at all until here
 
-    if let Some(axis) = axis {
-        // This dot product check handles an obscure case: if a collision callback
-        // overwrites our velocity so that we're moving /away/ from the object we
-        // hit, then there's no need to change it any more.  This happens with the
-        // moo form's charge in fox flux.
-        if direction.dot(axis) < 0 {
-            return SlideResult::Slid(direction - direction.project_on(axis));
+    /// Her sprite's affine matrix: mirrored when she faces left, and squashed for a moment after a
+    /// hard landing.
+    fn affine_matrix(&self) -> AffineMatrix {
+        let (scale_x, scale_y) = if self.squash_timer > 0 {
+            (SQUASH_SCALE_X, SQUASH_SCALE_Y)
         }
-        else {
-            return SlideResult::Slid(direction);
+        else if self.facing_left {
+            (1.into(), 1.into())
         }
+        else {
+            return AffineMatrix::identity();
+        };
+        let scale_x = if self.facing_left { -scale_x } else { scale_x };
+        AffineMatrix::from_rotation_scale(Angle::ZERO, scale_x, scale_y)
     }
-    else {
-        return SlideResult::Stuck;
+
+    /// What she's standing on, or None if she's in midair.
+    fn ground(&self, game: &Game) -> Option<Material> {
+        let contacts = contacts(game.place, &game.tile_colliders, &game.actors, |id| game.shape_of(id), &self.shape, self.solid_layers(), self.blockmap_id, CONTACT_TOLERANCE);
+        contacts.nearest(Surface::Floor).map(|touch| touch.material)
     }
-}
 
+    /// Push her a little way out of whatever she's stuck inside.  Takes a few frames to get all
+    /// the way out of something big.
+    fn depenetrate(&mut self, game: &mut Game) {
+        let push = match depenetration(game.place, &game.tile_colliders, &game.actors, |id| game.shape_of(id), &self.shape, self.solid_layers(), self.blockmap_id) {
+            Some(Depenetration::Push(push)) => push,
+            Some(Depenetration::Wedged(crush)) => {
+                // No way out, same as being squashed while moving
+                self.maybe_stuck = false;
+                self.crushed(game, crush);
+                return;
+            }
+            None => {
+                self.maybe_stuck = false;
+                return;
+            }
+        };
+
+        let push = if push.length() > DEPENETRATION_SPEED {
+            push.normalize() * DEPENETRATION_SPEED
+        }
+        else {
+            push
+        };
+        self.move_by(game, push);
+    }
 
+    /// Turn to face left or right, mirroring her hitbox to match.  If the mirrored hitbox would
+    /// end up inside a wall (or anything else solid, like a crate), she stays facing the way she
+    /// was, and this returns false.
+    fn set_facing(&mut self, game: &mut Game, facing_left: bool) -> bool {
+        if facing_left == self.facing_left {
+            return true;
+        }
+
+        let shape = self.shape.flipped_x(self.position.x);
+        if ! overlapping(game.place, &game.actors, |id| game.shape_of(id), &shape, self.solid_layers(), self.blockmap_id).is_empty() {
+            return false;
+        }
+
+        self.shape = shape;
+        self.bbox = flip_rect_x(&self.bbox, 0.into());
+        self.facing_left = facing_left;
+        self.update_blockmap(game);
+        true
+    }
+}
 
 impl Entity for Lexy {
-    fn update(&mut self, game: &Game) {
+    fn update(&mut self, game: &mut Game) {
         // gravity or whatever
-        self.velocity.y += Fixed::promote(16) / 75;
+        self.velocity.y += GRAVITY;
 
         let old_sprite_index = self.sprite_index;
         if self.velocity.x == 0 {
@@ -375,107 +533,65 @@ impl Entity for Lexy {
         let mut dy = self.velocity.y;
 
         let movement = self.velocity.clone();
-        self.nudge(movement);
+        self.nudge(game, movement);
 
-        // update position i guess?  assumes slot 0!
-        // FIXME this should very much be done in shadow oam and copied at next vblank
-        let sx = self.position.x - (if self.facing_left { 32 - self.anchor.x } else { self.anchor.x }) - game.camera.position.x;
-        let sy = self.position.y - self.anchor.y - game.camera.position.y;
-        unsafe {
-            (0x0700_0000 as *mut u16).write_volatile(sy.to_sprite_offset_y() | 0x2000u16 | 0x8000u16);
-            (0x0700_0002 as *mut u16).write_volatile(sx.to_sprite_offset_x() | 0xc000u16 | if self.facing_left { 0x1000u16 } else { 0u16 });
-            (0x0700_0004 as *mut u16).write_volatile(0u16 | 0x0400u16);
+        if self.squash_timer > 0 {
+            self.squash_timer -= 1;
         }
-    }
-
-    fn collider_sweep(&self, shape: &Polygon, attempted: Vector, collisions: &mut CollisionVec /*, pass_callback */) -> Vector {
-        let xbbox = shape.extended_bbox(attempted);
-        // Check out the tilemap
-        for ty in xbbox.min_y().to_tile_coord() .. (xbbox.max_y().to_tile_coord() + 1) {
-            for tx in xbbox.min_x().to_tile_coord() .. (xbbox.max_x().to_tile_coord() + 1) {
-                let tid = TEST_PLACE.tiles[ty][tx];
-                // TODO well really this should be...  if there's a /shape/
-                if ! TEST_PLACE.tileset.tiles[tid as usize].solid {
-                    continue;
-                }
-
-                // FIXME merely constructing one of these takes 0.4%, this is silly
-                let tile_polygon = Polygon::from_rect(rect(tx as i16 * 16, ty as i16 * 16, 16, 16));
-                let maybe_hit = shape.slide_towards(&tile_polygon, attempted);
-                if let Some(hit) = maybe_hit {
-                    collisions.push(hit);
-                }
-            }
+        if movement.y >= LANDING_SQUASH_SPEED && self.velocity.y == 0 {
+            self.squash_timer = SQUASH_FRAMES;
         }
 
-        // FIXME klinklang sorts by touchdist then touchtype, but i thought touchdist was
-        // meaningless??
-        collisions.as_mut_slice().sort_unstable_by_key(|collision| collision.touchdist);
-
-        // Look through the objects we'll hit, in the order we'll /touch/ them,
-        // and stop at the first that blocks us
-        let mut allowed_amount = None;
-        let mut allowed_movement = attempted;
-        let mut trim_collisions_to = collisions.len();
-        for (i, collision) in collisions.iter().enumerate() {
-            // FIXME collision.attempted = attempted
-
-            // If we've already found something that blocks us, and this
-            // collision requires moving further, then stop here.  This allows
-            // for ties
-            if let Some(allowed_amount) = allowed_amount {
-                if allowed_amount < collision.amount {
-                    trim_collisions_to = i;
-                    break;
-                }
+        // Landing on something bouncy sends her back up
+        if movement.y >= MIN_BOUNCE_SPEED && self.velocity.y == 0 {
+            if let Some(Material::Bouncy(restitution)) = self.ground(game) {
+                self.velocity.y = -movement.y * restitution;
             }
+        }
 
-            // Check if the other shape actually blocks us
-            /* XXX no need for this yet; if there's a shape, it blocks
-            local passable = pass_callback and pass_callback(collision)
-            if passable == 'retry' then
-                -- Special case: the other object just moved, so keep moving
-                -- and re-evaluate when we hit it again.  Useful for pushing.
-                if i > 1 and collisions[i - 1].shape == collision.shape then
-                    -- To avoid loops, don't retry a shape twice in a row
-                    passable = false
-                else
-                    local new_collision = shape:slide_towards(collision.shape, attempted)
-                    if new_collision then
-                        new_collision.shape = collision.shape
-                        for j = i + 1, #collisions + 1 do
-                            if j > #collisions or not _collision_sort(collisions[j], new_collision) then
-                                table.insert(collisions, j, new_collision)
-                                break
-                            end
-                        end
-                    end
-                end
-            end
-            */
-            let passable = false;
+        if self.position.y > game.place.kill_plane() {
+            self.respawn(game, self.checkpoint);
+        }
 
-            // If we're hitting the object and it's not passable, stop here
-            if allowed_amount.is_none() && ! passable && collision.touchtype == Contact::Collide {
-                allowed_amount = Some(collision.amount);
-                allowed_movement = collision.movement;
+        // update position i guess?  assumes slot 0!
+        // FIXME this should very much be done in shadow oam and copied at next vblank
+        let mut sx = self.position.x - (if self.facing_left { 32 - self.anchor.x } else { self.anchor.x }) - game.camera.position.x;
+        let mut sy = self.position.y - self.anchor.y - game.camera.position.y;
+        let attr0_extra;
+        let attr1_extra;
+        if let Some(slot) = self.affine {
+            self.affine_matrix().write(slot);
+            // Double-size sprites are centered in a box twice as big, so shift up and left by half
+            // the sprite size to keep her in the same place
+            sx -= 16;
+            sy -= 32;
+            if self.squash_timer > 0 {
+                // Scaling happens around the middle of the sprite, which pulls her feet (at the
+                // anchor) up towards it, so push her back down to keep them on the ground
+                sy += (self.anchor.y - 32) * (Fixed::from(1) - SQUASH_SCALE_Y);
             }
-
-            // Log the last contact with each shape
-            // XXX collision.passable = passable
-            // XXX hits[collision.shape] = collision
+            attr0_extra = ATTR0_AFFINE | ATTR0_DOUBLE_SIZE;
+            attr1_extra = slot.attr1_bits();
         }
-
-        while collisions.len() > trim_collisions_to {
-            collisions.pop();
+        else {
+            attr0_extra = 0u16;
+            attr1_extra = if self.facing_left { 0x1000u16 } else { 0u16 };
         }
+        unsafe {
+            (0x0700_0000 as *mut u16).write_volatile(sy.to_sprite_offset_y() | 0x2000u16 | 0x8000u16 | attr0_extra);
+            (0x0700_0002 as *mut u16).write_volatile(sx.to_sprite_offset_x() | 0xc000u16 | attr1_extra);
+            (0x0700_0004 as *mut u16).write_volatile(0u16 | 0x0400u16);
+        }
+    }
 
-        return allowed_movement;
+    fn crushed(&mut self, game: &mut Game, crush: Crush) {
+        spew!("crushed between {:?}", crush.normals);
+        self.respawn(game, self.checkpoint);
     }
 
     /// Move this entity through the world by some amount, respecting collision.  Returns the
     /// distance actually travelled.
-    fn nudge(&mut self, mut displacement: Vector) -> Vector {
+    fn nudge(&mut self, game: &mut Game, displacement: Vector) -> Vector {
         /*
         pushers = pushers or {}
         pushers[self] = true
@@ -489,76 +605,25 @@ impl Entity for Lexy {
         end
         */
 
-        // Main movement loop!  Try to slide in the direction of movement; if that
-        // fails, then try to project our movement along a surface we hit and
-        // continue, until we hit something head-on or run out of movement.
-        // TODO rename a LOT of these variables and properties, maybe in LÖVE too
-        let mut total_movement = Vector::zero();
-        let mut stuck_counter = 0;
-        let mut hits = CollisionVec::new();
-        loop {
-            // TODO return hits up here?
-            //let (successful, hits) = self.collider_sweep(&lexy_polygon, displacement /*, pass_callback */);
-            hits.clear();
-            let successful = self.collider_sweep(&self.shape, displacement, &mut hits /*, pass_callback */);
-            self.shape.move_by(successful);
-            self.position += successful;
-            total_movement += successful;
-
-            /* XXX
-            if xxx_no_slide then
-                break
-            end
-            */
-            let remaining = displacement - successful;
-            // FIXME these values are completely arbitrary and i cannot justify them
-            if remaining.x.abs() * 16 < 1 && remaining.y.abs() * 16 < 1 {
-                break;
-            }
-
-            // FIXME this shouldn't be in here...  or should it?  only for self movement obviously
-            // but this seems like the right place?
-            match slide_along_normals(&hits, self.velocity) {
-                SlideResult::Stuck => {
-                    self.velocity = Vector::zero();
-                }
-                SlideResult::Slid(new_velocity) => {
-                    self.velocity = new_velocity;
-                }
-            }
-
-            // Find the allowed slide direction that's closest to the direction of movement.
-            // TODO maybe this should just be Option haha
-            match slide_along_normals(&hits, remaining) {
-                SlideResult::Stuck => {
-                    break;
-                }
-                SlideResult::Slid(direction) => {
-                    displacement = direction;
-                }
-            }
-
-            // FIXME why am i doing this twice
-            if displacement.x.abs() * 16 < 1 && displacement.y.abs() * 16 < 1 {
-                break;
-            }
+        // The shape and velocity are borrowed for the whole move, so the sweep can't go through
+        // self
+        let (filter, layers, ignore) = (self.filter, self.solid_layers(), self.blockmap_id);
+        let world = &*game;
+        let nudge = slide::nudge(&mut self.shape, displacement, &mut self.velocity, |shape, attempted| {
+            collider_sweep(world, filter, layers, ignore, shape, attempted /*, pass_callback */)
+        });
+        if nudge.overlapped {
+            // Get out of whatever we're inside next frame
+            self.maybe_stuck = true;
+        }
+        if nudge.truncated {
+            spew!("too many collisions moving by {:?}; dropped some", displacement);
+        }
+        self.position += nudge.movement;
+        self.update_blockmap(game);
 
-            // Automatically break if we don't move for three iterations -- not
-            // moving once is okay because we might slide, but three indicates a
-            // bad loop somewhere
-            // XXX well, wait, aren't we only REALLY stuck if the remaining movement didn't get
-            // smaller (or at least, change in some way at all)?  but i don't want to move more
-            // than 3 times anyway so maybe it's ok
-            if _is_vector_almost_zero(successful) {
-                stuck_counter += 1;
-                if stuck_counter >= 3 {
-                    // FIXME interesting!  i get this when jumping against the crate in a corner in
-                    // tech-1; i think because clocks can't handle single angles correctly, so this
-                    // is the same problem as walking down a hallway exactly your own height -- is
-                    // this still the case?
-                    break;
-                }
-            }
+        if let Some(crush) = nudge.crush {
+            self.crushed(game, crush);
         }
 
         /* XXX cargo not supported
@@ -575,32 +640,141 @@ impl Entity for Lexy {
         pushers[self] = nil
         */
 
-        return total_movement//, hits
+        return nudge.movement//, hits
+    }
+}
+
+/// Move `value` towards `target`, by at most `step`.
+fn approach(value: Fixed, target: Fixed, step: Fixed) -> Fixed {
+    if value < target {
+        (value + step).min(target)
+    }
+    else {
+        (value - step).max(target)
     }
 }
 
 fn step(game: &mut Game, lexy: &mut Lexy) {
     let input = read_key_input();
 
+    let walk;
     if input.left() {
-        lexy.velocity.x = (-3).into();
-        lexy.facing_left = true;
+        walk = -WALK_SPEED;
+        lexy.set_facing(game, true);
     }
     else if input.right() {
-        lexy.velocity.x = 3.into();
-        lexy.facing_left = false;
+        walk = WALK_SPEED;
+        lexy.set_facing(game, false);
     }
     else {
-        lexy.velocity.x = 0.into();
+        walk = 0.into();
     }
-    lexy.velocity.x /= 2;
+
+    // What she's standing on changes how she gets around.  In midair she has full control, which
+    // is a bit generous, but it's what she had before there was anything else
+    let ground = lexy.ground(game);
+    lexy.velocity.x = match ground {
+        Some(Material::Ice) => approach(lexy.velocity.x, walk, ICE_ACCELERATION),
+        Some(Material::Sticky) => walk / 2,
+        Some(Material::Conveyor(speed)) => walk + speed,
+        _ => walk,
+    };
 
     if input.up() {
-        if lexy.velocity.y == 0 {
-            lexy.velocity.y -= 4;
+        // Only jump off solid ground -- a vertical velocity of zero also happens at the top of a
+        // jump
+        if lexy.velocity.y >= 0 {
+            match ground {
+                Some(Material::Sticky) => lexy.velocity.y = -JUMP_SPEED / 2,
+                Some(_) => lexy.velocity.y = -JUMP_SPEED,
+                None => {}
+            }
         }
     }
     if input.down() {
         //lexy.velocity.y = 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::WorldWhole;
+
+    /// The test place, ready to go, with Lexy standing on the floor at the given x.
+    fn lexy_on_floor_at(x: WorldWhole) -> (Game, Lexy) {
+        let mut game = Game::new(&TEST_PLACE);
+        game.tile_colliders.refresh(game.place);
+        let lexy = Lexy::new(&mut game, point2(x, 175));
+        (game, lexy)
+    }
+
+    #[test]
+    fn walks_into_crate() {
+        // Her right edge starts 10px short of the crate
+        let (mut game, mut lexy) = lexy_on_floor_at(80);
+        let moved = lexy.nudge(&mut game, vec2(16, 0));
+        assert_eq!(moved, vec2(10, 0));
+        assert_eq!(lexy.position, point2(90, 175));
+
+        // And can't walk through it from the other side, either
+        let (mut game, mut lexy) = lexy_on_floor_at(124);
+        let moved = lexy.nudge(&mut game, vec2(-16, 0));
+        assert_eq!(moved, vec2(-6, 0));
+    }
+
+    #[test]
+    fn cant_turn_into_crate() {
+        // Give her a hitbox that sticks out in front, so turning around actually moves it
+        let (mut game, mut lexy) = lexy_on_floor_at(120);
+        lexy.bbox = rect(-6, -26, 16, 27);
+        lexy.shape = Shape::Polygon(Polygon::from_rect(lexy.bbox.translate(&lexy.position.to_vector())));
+        lexy.update_blockmap(&mut game);
+
+        // Facing left would put her nose 2px into the crate
+        assert!(! lexy.set_facing(&mut game, true));
+        assert!(! lexy.facing_left);
+        assert_eq!(lexy.shape.bbox(), rect(114, 149, 16, 27));
+
+        // A little further away is fine
+        lexy.move_by(&mut game, vec2(4, 0));
+        assert!(lexy.set_facing(&mut game, true));
+        assert!(lexy.facing_left);
+        assert_eq!(lexy.shape.bbox(), rect(114, 149, 16, 27));
+    }
+
+    #[test]
+    fn pushed_out_of_crate() {
+        // Spawned with her right edge 3px inside the crate
+        let (mut game, mut lexy) = lexy_on_floor_at(93);
+        assert!(lexy.maybe_stuck);
+
+        // Out it comes, a bit at a time
+        lexy.depenetrate(&mut game);
+        assert_eq!(lexy.position, point2(91, 175));
+        assert!(lexy.maybe_stuck);
+        lexy.depenetrate(&mut game);
+        assert_eq!(lexy.position, point2(90, 175));
+        lexy.depenetrate(&mut game);
+        assert_eq!(lexy.position, point2(90, 175));
+        assert!(! lexy.maybe_stuck);
+    }
+
+    #[test]
+    fn ground_under_her() {
+        let (mut game, mut lexy) = lexy_on_floor_at(48);
+        assert_eq!(lexy.ground(&game), Some(Material::Normal));
+
+        // On the crate, which is an actor rather than a tile
+        lexy.move_by(&mut game, point2(104, 159) - lexy.position);
+        assert_eq!(lexy.ground(&game), Some(Material::Normal));
+
+        // On the springy pad, which has to come from the actor too
+        lexy.move_by(&mut game, point2(192, 167) - lexy.position);
+        assert_eq!(lexy.ground(&game), Some(Material::Bouncy(fixed!(0.75))));
+
+        // Hovering over it
+        lexy.move_by(&mut game, vec2(0, -8));
+        assert_eq!(lexy.ground(&game), None);
+    }
+}
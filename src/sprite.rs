@@ -0,0 +1,165 @@
+/// Sprite (OBJ) helpers.
+///
+/// So far this is just the affine side of things; plain attributes are still poked directly.
+
+use crate::angle::Angle;
+use crate::fixed::Fixed;
+
+/// Number of affine matrices OAM has room for.
+pub const AFFINE_MATRIX_COUNT: usize = 32;
+
+/// Attribute 0 bit that makes a sprite use an affine matrix.
+pub const ATTR0_AFFINE: u16 = 0x0100;
+/// Attribute 0 bit that doubles the size of an affine sprite's bounding box, so it can rotate or
+/// grow without being clipped.  Only meaningful alongside `ATTR0_AFFINE`.
+pub const ATTR0_DOUBLE_SIZE: u16 = 0x0200;
+
+const ATTR1_AFFINE_INDEX_SHIFT: u16 = 9;
+
+// The four parameters of each matrix are scattered across the otherwise unused fourth halfword of
+// four consecutive OAM entries.
+const OAM_AFFINE_BASE: usize = 0x0700_0006;
+const OAM_AFFINE_STRIDE: usize = 32;
+const OAM_AFFINE_PARAM_STRIDE: usize = 8;
+
+/// Smallest scale (either way) a matrix can have.  The hardware stores the /inverse/ as 8.8, which
+/// tops out just under 128, so anything smaller than this would wrap around -- and zero would be a
+/// division by zero.
+const MIN_AFFINE_SCALE: Fixed = Fixed::from_bits(3);
+
+/// One of the OBJ affine matrices.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct AffineSlot(u8);
+
+impl AffineSlot {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+
+    /// Attribute 1 bits that select this matrix.  These overlap the flip bits, so an affine sprite
+    /// has to do its flipping in the matrix instead.
+    pub fn attr1_bits(self) -> u16 {
+        (self.0 as u16) << ATTR1_AFFINE_INDEX_SHIFT
+    }
+}
+
+/// Keeps track of which affine matrices are in use.
+pub struct AffineAllocator {
+    used: u32,
+}
+
+impl AffineAllocator {
+    pub fn new() -> Self {
+        AffineAllocator{ used: 0 }
+    }
+
+    /// Claim a free matrix, or return None if all of them are taken.
+    pub fn allocate(&mut self) -> Option<AffineSlot> {
+        if self.used.count_ones() as usize >= AFFINE_MATRIX_COUNT {
+            return None;
+        }
+        let index = (!self.used).trailing_zeros();
+        self.used |= 1 << index;
+        Some(AffineSlot(index as u8))
+    }
+
+    /// Give a matrix back, e.g. when whatever was using it goes away.  Whoever had it had better
+    /// stop drawing with it, too.
+    pub fn free(&mut self, slot: AffineSlot) {
+        self.used &= !(1 << slot.0);
+    }
+}
+
+/// An OBJ affine matrix.
+///
+/// Note that the hardware uses this to map screen space to texture space, so it's the /inverse/
+/// of the transformation you actually see.  The constructors take care of that.
+#[derive(Clone, Copy, Debug)]
+pub struct AffineMatrix {
+    pub pa: Fixed,
+    pub pb: Fixed,
+    pub pc: Fixed,
+    pub pd: Fixed,
+}
+
+impl AffineMatrix {
+    pub fn identity() -> Self {
+        AffineMatrix{
+            pa: 1.into(),
+            pb: 0.into(),
+            pc: 0.into(),
+            pd: 1.into(),
+        }
+    }
+
+    /// Scale by the given factors, then rotate by `angle`.  A negative `scale_x` mirrors the
+    /// sprite, which is how affine sprites flip.  Scales closer to zero than `MIN_AFFINE_SCALE`
+    /// are clamped to it, keeping their sign.
+    pub fn from_rotation_scale(angle: Angle, scale_x: Fixed, scale_y: Fixed) -> Self {
+        let (scale_x, scale_y) = (clamp_scale(scale_x), clamp_scale(scale_y));
+        let (sin, cos) = angle.sin_cos();
+        AffineMatrix{
+            pa: cos / scale_x,
+            pb: sin / scale_x,
+            pc: -sin / scale_y,
+            pd: cos / scale_y,
+        }
+    }
+
+    /// Copy this matrix into OAM.
+    pub fn write(&self, slot: AffineSlot) {
+        let base = OAM_AFFINE_BASE + slot.index() * OAM_AFFINE_STRIDE;
+        for (i, param) in [self.pa, self.pb, self.pc, self.pd].iter().enumerate() {
+            unsafe {
+                ((base + i * OAM_AFFINE_PARAM_STRIDE) as *mut i16).write_volatile(param.to_bits() as i16);
+            }
+        }
+    }
+}
+
+fn clamp_scale(scale: Fixed) -> Fixed {
+    if scale < 0 {
+        scale.min(-MIN_AFFINE_SCALE)
+    }
+    else {
+        scale.max(MIN_AFFINE_SCALE)
+    }
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn allocate_and_free() {
+        let mut matrices = AffineAllocator::new();
+        let first = matrices.allocate().unwrap();
+        let second = matrices.allocate().unwrap();
+        assert_eq!((first.index(), second.index()), (0, 1));
+
+        // A freed slot is the next one handed out
+        matrices.free(first);
+        assert_eq!(matrices.allocate(), Some(first));
+        assert_eq!(matrices.allocate().map(AffineSlot::index), Some(2));
+    }
+
+    #[test]
+    fn exhaust_every_slot() {
+        let mut matrices = AffineAllocator::new();
+        let slots: Vec<_> = (0 .. AFFINE_MATRIX_COUNT).map(|_| matrices.allocate().unwrap()).collect();
+        for (i, slot) in slots.iter().enumerate() {
+            assert_eq!(slot.index(), i);
+        }
+        assert_eq!(matrices.allocate(), None);
+
+        matrices.free(slots[17]);
+        assert_eq!(matrices.allocate(), Some(slots[17]));
+        assert_eq!(matrices.allocate(), None);
+
+        for &slot in &slots {
+            matrices.free(slot);
+        }
+        assert_eq!(matrices.allocate(), Some(slots[0]));
+    }
+}
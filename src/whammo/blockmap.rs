@@ -0,0 +1,296 @@
+/// Broadphase for actors: a coarse grid that remembers which shapes might be in each block, so
+/// finding what's near something doesn't mean checking everything against everything.
+///
+/// Sets of shapes are bitmasks of their ids, which keeps this small and means everything comes
+/// out lowest id first, so the results don't depend on what order things happened to move in.
+
+use num_traits::clamp;
+
+use crate::geom::{Rect, RectExt, WorldUnit};
+use super::layers::{CollisionFilter, Layers};
+use super::materials::Material;
+
+/// Most shapes a blockmap can track at once.  Has to fit in a ShapeSet.
+pub const MAX_BLOCKMAP_SHAPES: usize = 32;
+
+/// Blocks are 64×64, i.e. 4×4 tiles.
+const BLOCK_SIZE_BITS: usize = 6;
+/// Number of blocks along each side, enough for 1024×1024.  Anything further out gets lumped in
+/// with the blocks along the edge.
+const BLOCKMAP_DIMENSION: usize = 16;
+
+/// Handle for a shape in a blockmap.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub struct ShapeId(u8);
+
+impl ShapeId {
+    pub fn index(self) -> usize {
+        self.0 as usize
+    }
+}
+
+/// A set of shape ids.  Iterates in ascending order.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct ShapeSet(u32);
+
+impl ShapeSet {
+    pub fn new() -> Self {
+        ShapeSet(0)
+    }
+
+    pub fn is_empty(self) -> bool {
+        self.0 == 0
+    }
+
+    pub fn contains(self, id: ShapeId) -> bool {
+        self.0 & (1 << id.0) != 0
+    }
+
+    pub fn insert(&mut self, id: ShapeId) {
+        self.0 |= 1 << id.0;
+    }
+
+    pub fn remove(&mut self, id: ShapeId) {
+        self.0 &= !(1 << id.0);
+    }
+
+    pub fn union(self, other: ShapeSet) -> ShapeSet {
+        ShapeSet(self.0 | other.0)
+    }
+
+    pub fn intersection(self, other: ShapeSet) -> ShapeSet {
+        ShapeSet(self.0 & other.0)
+    }
+
+    /// Everything in this set that isn't in the other one.
+    pub fn difference(self, other: ShapeSet) -> ShapeSet {
+        ShapeSet(self.0 & !other.0)
+    }
+
+    pub fn iter(self) -> ShapeSetIter {
+        ShapeSetIter(self.0)
+    }
+}
+
+pub struct ShapeSetIter(u32);
+
+impl Iterator for ShapeSetIter {
+    type Item = ShapeId;
+
+    fn next(&mut self) -> Option<ShapeId> {
+        if self.0 == 0 {
+            return None;
+        }
+        let index = self.0.trailing_zeros();
+        self.0 &= self.0 - 1;
+        Some(ShapeId(index as u8))
+    }
+}
+
+/// Inclusive range of blocks something covers.
+#[derive(Clone, Copy, PartialEq, Eq)]
+struct BlockRange {
+    x0: u8,
+    y0: u8,
+    x1: u8,
+    y1: u8,
+}
+
+impl BlockRange {
+    fn covering(rect: &Rect) -> Self {
+        BlockRange{
+            x0: block_coord(rect.min_x()),
+            y0: block_coord(rect.min_y()),
+            x1: block_coord(rect.max_x()),
+            y1: block_coord(rect.max_y()),
+        }
+    }
+}
+
+fn block_coord(n: WorldUnit) -> u8 {
+    let block = n.to_bits() >> (WorldUnit::FRACTIONAL_BITS + BLOCK_SIZE_BITS);
+    clamp(block, 0, BLOCKMAP_DIMENSION as i32 - 1) as u8
+}
+
+#[derive(Clone, Copy)]
+struct Entry {
+    bbox: Rect,
+    blocks: BlockRange,
+    filter: CollisionFilter,
+    material: Material,
+}
+
+pub struct Blockmap {
+    blocks: [[ShapeSet; BLOCKMAP_DIMENSION]; BLOCKMAP_DIMENSION],
+    entries: [Option<Entry>; MAX_BLOCKMAP_SHAPES],
+}
+
+impl Blockmap {
+    pub fn new() -> Self {
+        Blockmap{
+            blocks: [[ShapeSet::new(); BLOCKMAP_DIMENSION]; BLOCKMAP_DIMENSION],
+            entries: [None; MAX_BLOCKMAP_SHAPES],
+        }
+    }
+
+    /// Start tracking a shape, given its bbox -- or, for something that's about to move, its
+    /// `extended_bbox` -- and what layers it's on.  Returns None if the blockmap is full.
+    pub fn add(&mut self, bbox: Rect, filter: CollisionFilter) -> Option<ShapeId> {
+        let index = self.entries.iter().position(|entry| entry.is_none())?;
+        let id = ShapeId(index as u8);
+        let blocks = BlockRange::covering(&bbox);
+        self.entries[index] = Some(Entry{ bbox, blocks, filter, material: Material::Normal });
+        self.mark(id, blocks, true);
+        Some(id)
+    }
+
+    pub fn remove(&mut self, id: ShapeId) {
+        if let Some(entry) = self.entries[id.index()].take() {
+            self.mark(id, entry.blocks, false);
+        }
+    }
+
+    /// Let the blockmap know a shape has moved or is about to.  Cheap when it stays within the
+    /// same blocks, which is most of the time.
+    pub fn update(&mut self, id: ShapeId, bbox: Rect) {
+        let old_blocks = match self.entries[id.index()] {
+            Some(ref mut entry) => {
+                entry.bbox = bbox;
+                entry.blocks
+            }
+            None => return,
+        };
+
+        let blocks = BlockRange::covering(&bbox);
+        if blocks != old_blocks {
+            self.mark(id, old_blocks, false);
+            self.mark(id, blocks, true);
+            if let Some(ref mut entry) = self.entries[id.index()] {
+                entry.blocks = blocks;
+            }
+        }
+    }
+
+    pub fn filter(&self, id: ShapeId) -> Option<CollisionFilter> {
+        self.entries[id.index()].map(|entry| entry.filter)
+    }
+
+    pub fn set_filter(&mut self, id: ShapeId, filter: CollisionFilter) {
+        if let Some(ref mut entry) = self.entries[id.index()] {
+            entry.filter = filter;
+        }
+    }
+
+    /// What a shape is made of, for anything standing on it.  Everything starts out `Normal`.
+    pub fn material(&self, id: ShapeId) -> Option<Material> {
+        self.entries[id.index()].map(|entry| entry.material)
+    }
+
+    pub fn set_material(&mut self, id: ShapeId, material: Material) {
+        if let Some(ref mut entry) = self.entries[id.index()] {
+            entry.material = material;
+        }
+    }
+
+    fn mark(&mut self, id: ShapeId, blocks: BlockRange, present: bool) {
+        for row in &mut self.blocks[blocks.y0 as usize ..= blocks.y1 as usize] {
+            for set in &mut row[blocks.x0 as usize ..= blocks.x1 as usize] {
+                if present {
+                    set.insert(id);
+                }
+                else {
+                    set.remove(id);
+                }
+            }
+        }
+    }
+
+    fn candidates_in(&self, blocks: BlockRange) -> ShapeSet {
+        let mut candidates = ShapeSet::new();
+        for row in &self.blocks[blocks.y0 as usize ..= blocks.y1 as usize] {
+            for &set in &row[blocks.x0 as usize ..= blocks.x1 as usize] {
+                candidates = candidates.union(set);
+            }
+        }
+        candidates
+    }
+
+    /// All the shapes whose bboxes overlap or touch the given rect.
+    pub fn neighbors(&self, bbox: &Rect) -> ShapeSet {
+        self.neighbors_in(bbox, Layers::ALL)
+    }
+
+    /// Same as `neighbors`, but only shapes in one of the given layers.
+    pub fn neighbors_in(&self, bbox: &Rect, layers: Layers) -> ShapeSet {
+        let mut neighbors = ShapeSet::new();
+        for id in self.candidates_in(BlockRange::covering(bbox)).iter() {
+            if let Some(ref entry) = self.entries[id.index()] {
+                if entry.filter.category.intersects(layers) && entry.bbox.touches(bbox) {
+                    neighbors.insert(id);
+                }
+            }
+        }
+        neighbors
+    }
+
+    /// Call `f` with every pair of shapes whose bboxes overlap or touch, skipping pairs whose
+    /// layers say they ignore each other.  Each pair comes up once, lower id first, sorted by the
+    /// first id and then the second.
+    pub fn for_each_pair<F: FnMut(ShapeId, ShapeId)>(&self, mut f: F) {
+        for (index, entry) in self.entries.iter().enumerate() {
+            let entry = match entry {
+                Some(entry) => entry,
+                None => continue,
+            };
+            let id = ShapeId(index as u8);
+            for other_id in self.candidates_in(entry.blocks).iter() {
+                if other_id <= id {
+                    continue;
+                }
+                if let Some(ref other) = self.entries[other_id.index()] {
+                    if entry.filter.interacts_with(other.filter) && entry.bbox.touches(&other.bbox) {
+                        f(id, other_id);
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use arrayvec::ArrayVec;
+    use crate::geom::rect;
+
+    #[test]
+    fn pairs_come_out_in_order() {
+        let mut blockmap = Blockmap::new();
+        let player = CollisionFilter::new(Layers::PLAYER, Layers::TERRAIN | Layers::ENEMY);
+        let enemy = CollisionFilter::new(Layers::ENEMY, Layers::TERRAIN | Layers::PLAYER);
+        // Added out of order on purpose, across a block boundary
+        let wall = blockmap.add(rect(60, 0, 8, 64), CollisionFilter::TERRAIN).unwrap();
+        let lexy = blockmap.add(rect(48, 8, 12, 27), player).unwrap();
+        let slime = blockmap.add(rect(68, 16, 16, 16), enemy).unwrap();
+        let other_slime = blockmap.add(rect(80, 16, 16, 16), enemy).unwrap();
+        // Way off by itself
+        blockmap.add(rect(500, 500, 16, 16), enemy).unwrap();
+
+        let mut pairs = ArrayVec::<[(ShapeId, ShapeId); 8]>::new();
+        blockmap.for_each_pair(|a, b| pairs.push((a, b)));
+        // Slimes ignore each other, and Lexy's nowhere near them
+        assert_eq!(&pairs[..], &[(wall, lexy), (wall, slime)]);
+
+        // Moving her past the wall changes what's in there, but not the order
+        blockmap.update(lexy, rect(70, 8, 12, 27));
+        pairs.clear();
+        blockmap.for_each_pair(|a, b| pairs.push((a, b)));
+        assert_eq!(&pairs[..], &[(wall, slime), (lexy, slime), (lexy, other_slime)]);
+
+        // And neighbors_in only finds what's in the asked-for layers
+        let found: ArrayVec<[ShapeId; 8]> = blockmap.neighbors_in(&rect(56, 0, 32, 32), Layers::TERRAIN).iter().collect();
+        assert_eq!(&found[..], &[wall]);
+        let found: ArrayVec<[ShapeId; 8]> = blockmap.neighbors(&rect(56, 0, 32, 32)).iter().collect();
+        assert_eq!(&found[..], &[wall, lexy, slime, other_slime]);
+    }
+}
@@ -0,0 +1,75 @@
+/// Collision layers: which kinds of things notice which other kinds of things.
+///
+/// Every shape has a category (what it is) and a mask (what it cares about).  Two shapes only
+/// interact if each one's mask includes the other's category, so e.g. a pickup can ignore
+/// projectiles without the projectile having to know anything about pickups.
+
+use core::ops::{BitAnd, BitOr};
+
+/// A set of collision layers.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Layers(u8);
+
+impl Layers {
+    pub const NONE: Layers = Layers(0);
+    pub const PLAYER: Layers = Layers(1 << 0);
+    pub const ENEMY: Layers = Layers(1 << 1);
+    pub const PROJECTILE: Layers = Layers(1 << 2);
+    /// The tilemap, and anything else that's meant to act like part of it
+    pub const TERRAIN: Layers = Layers(1 << 3);
+    pub const PICKUP: Layers = Layers(1 << 4);
+    pub const ALL: Layers = Layers(0xff);
+
+    pub fn contains(self, other: Layers) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    pub fn intersects(self, other: Layers) -> bool {
+        self.0 & other.0 != 0
+    }
+}
+
+impl BitOr for Layers {
+    type Output = Layers;
+
+    fn bitor(self, other: Layers) -> Layers {
+        Layers(self.0 | other.0)
+    }
+}
+
+impl BitAnd for Layers {
+    type Output = Layers;
+
+    fn bitand(self, other: Layers) -> Layers {
+        Layers(self.0 & other.0)
+    }
+}
+
+/// What a shape is, and what it collides with.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct CollisionFilter {
+    pub category: Layers,
+    pub mask: Layers,
+}
+
+impl CollisionFilter {
+    /// Solid ground.  Doesn't care what hits it, so it's up to the mover.
+    pub const TERRAIN: CollisionFilter = CollisionFilter{ category: Layers::TERRAIN, mask: Layers::ALL };
+
+    pub const fn new(category: Layers, mask: Layers) -> Self {
+        CollisionFilter{ category, mask }
+    }
+
+    /// Do these two interact at all?  Check this before bothering with `slide_towards`.
+    pub fn interacts_with(self, other: CollisionFilter) -> bool {
+        self.mask.intersects(other.category) && other.mask.intersects(self.category)
+    }
+}
+
+impl Default for CollisionFilter {
+    /// Something that is everything and collides with everything, i.e. what you got before there
+    /// were layers.
+    fn default() -> Self {
+        CollisionFilter{ category: Layers::ALL, mask: Layers::ALL }
+    }
+}
@@ -0,0 +1,23 @@
+/// What surfaces are made of, which changes how things behave when they stand on them.
+
+use crate::fixed::Fixed;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Material {
+    /// Plain old ground
+    Normal,
+    /// Hard to get going and hard to stop
+    Ice,
+    /// Slows down walking and jumping
+    Sticky,
+    /// Carries anything standing on it along at this horizontal speed
+    Conveyor(Fixed),
+    /// Bounces anything that lands on it back up, keeping this fraction of its speed
+    Bouncy(Fixed),
+}
+
+impl Default for Material {
+    fn default() -> Self {
+        Material::Normal
+    }
+}
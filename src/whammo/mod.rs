@@ -1,14 +1,127 @@
+pub mod blockmap;
+pub mod layers;
+pub mod materials;
+pub mod query;
+pub mod raycast;
 pub mod shapes;
+pub mod slide;
+pub mod tilemap;
+pub mod triggers;
 
 use arrayvec::ArrayVec;
 
-use crate::geom::Vector;
-use self::shapes::Collision;
+use crate::fixed::Fixed;
+use crate::geom::{TilePoint, Vector, VectorExt};
+use self::blockmap::ShapeId;
+use self::shapes::{Collision, Contact};
 
 const MAX_COLLISIONS: usize = 8;
 pub type CollisionVec = ArrayVec<[Collision; MAX_COLLISIONS]>;
 
+/// What happened when something tried to move: how far it actually got, and what it ran into.
 pub struct CollisionResult {
     pub allowed: Vector,
     pub collisions: CollisionVec,
+    /// Set if there were more collisions than would fit, in which case the least relevant ones
+    /// were dropped
+    pub truncated: bool,
+}
+
+impl CollisionResult {
+    pub fn new(allowed: Vector) -> Self {
+        CollisionResult{ allowed, collisions: CollisionVec::new(), truncated: false }
+    }
+
+    /// Record a collision.  If the list is already full, whichever collision is least relevant --
+    /// the one that happens furthest along the movement -- gets dropped to make room, which might
+    /// be this one.
+    pub fn add(&mut self, collision: Collision) {
+        let collision = match self.collisions.try_push(collision) {
+            Ok(()) => return,
+            Err(err) => err.element(),
+        };
+
+        self.truncated = true;
+        let relevance = |collision: &Collision| (collision.amount, collision.touchdist);
+        let worst = self.collisions.iter().enumerate()
+            .max_by_key(|(_, existing)| relevance(existing))
+            .map(|(i, _)| i);
+        if let Some(i) = worst {
+            if relevance(&collision) < relevance(&self.collisions[i]) {
+                self.collisions[i] = collision;
+            }
+        }
+    }
+}
+
+/// Something in the world a query found: a tile, or an actor's shape.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Target {
+    Tile(TilePoint),
+    Shape(ShapeId),
+}
+
+/// How squarely two surfaces have to face each other to crush something between them, as the
+/// cosine of the angle between their normals.  This is about 150°, so a narrow V is a crush but
+/// the corner of a room isn't.
+const CRUSH_DOT: Fixed = fixed!(-0.85);
+
+/// Something got squashed between two surfaces.  Both normals are unit length and point inwards,
+/// towards whatever got squashed.
+#[derive(Clone, Copy, Debug)]
+pub struct Crush {
+    pub normals: (Vector, Vector),
+}
+
+/// Collects the surfaces something runs into over the course of a move, to tell whether it's
+/// being squeezed between them.
+pub struct Squeeze {
+    normals: ArrayVec<[Vector; MAX_COLLISIONS]>,
+}
+
+impl Squeeze {
+    pub fn new() -> Self {
+        Squeeze{ normals: ArrayVec::new() }
+    }
+
+    /// Note every surface that actually stopped us.  Surfaces we merely slid along don't count,
+    /// or walking down a hallway exactly your own height would be fatal.
+    pub fn add_collisions(&mut self, collisions: &CollisionVec) {
+        for collision in collisions.iter() {
+            if collision.touchtype != Contact::Collide {
+                continue;
+            }
+            for &normal in collision.left_normal.iter().chain(collision.right_normal.iter()) {
+                self.add_normal(normal);
+            }
+        }
+    }
+
+    /// Note a surface by its normal, which needn't be unit length.  Something pushing us counts
+    /// too, with a normal pointing the way it's pushing.
+    pub fn add_normal(&mut self, normal: Vector) {
+        // Too short to normalize (which includes zero) means too small to push anything
+        if normal.square_length() == 0 {
+            return;
+        }
+        let normal = normal.normalize();
+        if self.normals.contains(&normal) {
+            return;
+        }
+        // If this fills up, we've hit a whole lot of stuff, and we probably already know whether
+        // we're crushed
+        let _ = self.normals.try_push(normal);
+    }
+
+    /// The first pair of surfaces that face each other, if any.
+    pub fn crush(&self) -> Option<Crush> {
+        for (i, &normal1) in self.normals.iter().enumerate() {
+            for &normal2 in &self.normals[i + 1 ..] {
+                if normal1.dot(normal2) <= CRUSH_DOT {
+                    return Some(Crush{ normals: (normal1, normal2) });
+                }
+            }
+        }
+        None
+    }
 }
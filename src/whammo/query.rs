@@ -0,0 +1,277 @@
+/// Asking what's in some part of the world, without having to move anything to find out.
+
+use arrayvec::ArrayVec;
+use num_traits::clamp;
+
+use crate::data::places::Place;
+use crate::fixed::Fixed;
+use crate::geom::{Point, Rect, Size, TilePoint, Vector, WorldUnit, tile_to_world_rect, tiles_touching, world_to_tile};
+use super::{Crush, Squeeze, Target};
+use super::blockmap::{Blockmap, ShapeId};
+use super::layers::Layers;
+use super::materials::Material;
+use super::shapes::{Polygon, Shape};
+use super::tilemap::TileColliders;
+
+/// Most results an overlap query will return.  Anything past this is dropped.
+pub const MAX_OVERLAPS: usize = 16;
+pub type OverlapVec = ArrayVec<[Target; MAX_OVERLAPS]>;
+
+/// All the solid tiles overlapping a shape, in reading order.  Tiles it merely touches don't count.
+pub fn overlapping_tiles(place: &Place, shape: &Shape) -> OverlapVec {
+    let mut overlaps = OverlapVec::new();
+    add_overlapping_tiles(place, shape, &mut overlaps);
+    overlaps
+}
+
+fn add_overlapping_tiles(place: &Place, shape: &Shape, overlaps: &mut OverlapVec) {
+    let tiles = tiles_touching(shape.bbox());
+    for ty in tiles.min_y() .. tiles.max_y() {
+        for tx in tiles.min_x() .. tiles.max_x() {
+            let tile = TilePoint::new(tx, ty);
+            if ! place.tile_at(tile).solid || ! shape.overlaps_box(&tile_to_world_rect(tile)) {
+                continue;
+            }
+            if overlaps.try_push(Target::Tile(tile)).is_err() {
+                return;
+            }
+        }
+    }
+}
+
+/// Everything overlapping a shape: solid tiles first, in reading order, then actors in id order.
+/// `shape_of` looks up the shape for an id in `actors`, `layers` is what to look for (tiles are
+/// `TERRAIN`), and `ignore` is usually whoever's asking.
+pub fn overlapping<'a, F>(place: &Place, actors: &Blockmap, shape_of: F, shape: &Shape, layers: Layers, ignore: Option<ShapeId>) -> OverlapVec
+    where F: Fn(ShapeId) -> Option<&'a Shape>
+{
+    let mut overlaps = OverlapVec::new();
+    if layers.intersects(Layers::TERRAIN) {
+        add_overlapping_tiles(place, shape, &mut overlaps);
+    }
+    for id in actors.neighbors_in(&shape.bbox(), layers).iter() {
+        if Some(id) == ignore {
+            continue;
+        }
+        let overlapped = match shape_of(id) {
+            Some(other) => shape.overlaps(other),
+            None => false,
+        };
+        if overlapped && overlaps.try_push(Target::Shape(id)).is_err() {
+            break;
+        }
+    }
+    overlaps
+}
+
+/// Same as `overlapping`, for a plain rect.
+pub fn overlapping_rect<'a, F>(place: &Place, actors: &Blockmap, shape_of: F, rect: &Rect, layers: Layers, ignore: Option<ShapeId>) -> OverlapVec
+    where F: Fn(ShapeId) -> Option<&'a Shape>
+{
+    overlapping(place, actors, shape_of, &Shape::Polygon(Polygon::from_rect(*rect)), layers, ignore)
+}
+
+/// Is there anything solid at this point, tile or actor, in the given layers?
+pub fn is_point_solid<'a, F>(place: &Place, actors: &Blockmap, shape_of: F, point: Point, layers: Layers) -> bool
+    where F: Fn(ShapeId) -> Option<&'a Shape>
+{
+    if layers.intersects(Layers::TERRAIN) && place.tile_at(world_to_tile(point)).solid {
+        return true;
+    }
+    let here = Rect::new(point, Size::zero());
+    actors.neighbors_in(&here, layers).iter().any(|id| shape_of(id).map_or(false, |shape| shape.contains_point(point)))
+}
+
+/// If a shape has ended up inside things (say, after spawning or teleporting, or when the tiles
+/// change around it), which way to move it to get it out again, or None if it's fine where it is.
+/// Arguments are the same as for `contacts`.
+///
+/// Each thing it's stuck in gets its own shortest way out, and those are combined by taking the
+/// biggest push each way along each axis, so being wedged between two things pulls in both
+/// directions and doesn't get anywhere -- that comes back as `Wedged`, since it's well and truly
+/// stuck.  Pushing out of one thing can also push into another, so it's worth calling this again
+/// after moving.
+pub fn depenetration<'a, F>(place: &Place, tile_colliders: &TileColliders, actors: &Blockmap, shape_of: F, shape: &Shape, layers: Layers, ignore: Option<ShapeId>) -> Option<Depenetration>
+    where F: Fn(ShapeId) -> Option<&'a Shape>
+{
+    let mut push = PushAccumulator::new();
+    let bbox = shape.bbox();
+    if layers.intersects(Layers::TERRAIN) {
+        tile_colliders.each_touching(place, tiles_touching(bbox), |collider| {
+            push.add(shape.penetration_box(collider));
+        });
+    }
+    for id in actors.neighbors_in(&bbox, layers).iter() {
+        if Some(id) == ignore {
+            continue;
+        }
+        if let Some(other) = shape_of(id) {
+            push.add(shape.penetration(other));
+        }
+    }
+    push.finish()
+}
+
+/// The way out of whatever something's stuck inside.
+#[derive(Clone, Copy, Debug)]
+pub enum Depenetration {
+    /// Move this way to get out, or at least to get somewhere better
+    Push(Vector),
+    /// Pushed equally from opposite sides, so there's no way out
+    Wedged(Crush),
+}
+
+/// Adds up pushes, keeping the biggest in each direction.
+struct PushAccumulator {
+    any: bool,
+    min: Vector,
+    max: Vector,
+}
+
+impl PushAccumulator {
+    fn new() -> Self {
+        PushAccumulator{ any: false, min: Vector::zero(), max: Vector::zero() }
+    }
+
+    fn add(&mut self, push: Option<Vector>) {
+        if let Some(push) = push {
+            self.any = true;
+            self.min = Vector::new(self.min.x.min(push.x), self.min.y.min(push.y));
+            self.max = Vector::new(self.max.x.max(push.x), self.max.y.max(push.y));
+        }
+    }
+
+    fn finish(self) -> Option<Depenetration> {
+        if ! self.any {
+            return None;
+        }
+        let push = self.min + self.max;
+        if push != Vector::zero() {
+            return Some(Depenetration::Push(push));
+        }
+
+        // Every push was cancelled out by one going the opposite way, which is exactly a crush
+        let mut squeeze = Squeeze::new();
+        squeeze.add_normal(Vector::new(self.min.x, 0.into()));
+        squeeze.add_normal(Vector::new(self.max.x, 0.into()));
+        squeeze.add_normal(Vector::new(0.into(), self.min.y));
+        squeeze.add_normal(Vector::new(0.into(), self.max.y));
+        Some(squeeze.crush().map_or(Depenetration::Push(push), Depenetration::Wedged))
+    }
+}
+
+/// How close a surface has to be to count as touching, by default.
+pub const CONTACT_TOLERANCE: WorldUnit = fixed!(0.5);
+
+/// Which way a surface faces, relative to whoever's touching it.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Surface {
+    Floor,
+    Ceiling,
+    /// A wall to our left, i.e. facing right
+    LeftWall,
+    /// A wall to our right, i.e. facing left
+    RightWall,
+}
+
+/// A surface something is resting on or brushing against.
+#[derive(Clone, Copy, Debug)]
+pub struct Touch {
+    pub target: Target,
+    /// Unit normal of the surface, pointing back towards whoever asked
+    pub normal: Vector,
+    /// How far away the surface is; zero means actually touching
+    pub gap: WorldUnit,
+    pub material: Material,
+}
+
+impl Touch {
+    /// What kind of surface this is.  45° slopes count as floors and ceilings.
+    pub fn surface(&self) -> Surface {
+        if self.normal.x.abs() <= -self.normal.y {
+            Surface::Floor
+        }
+        else if self.normal.x.abs() <= self.normal.y {
+            Surface::Ceiling
+        }
+        else if self.normal.x > 0 {
+            Surface::LeftWall
+        }
+        else {
+            Surface::RightWall
+        }
+    }
+}
+
+/// Everything something is touching.
+pub struct Contacts {
+    pub touches: ArrayVec<[Touch; MAX_OVERLAPS]>,
+}
+
+impl Contacts {
+    /// The nearest surface of the given kind, if we're touching one at all.
+    pub fn nearest(&self, surface: Surface) -> Option<&Touch> {
+        let mut best: Option<&Touch> = None;
+        for touch in self.touches.iter().filter(|touch| touch.surface() == surface) {
+            if best.map_or(true, |best| touch.gap < best.gap) {
+                best = Some(touch);
+            }
+        }
+        best
+    }
+
+    pub fn normal(&self, surface: Surface) -> Option<Vector> {
+        self.nearest(surface).map(|touch| touch.normal)
+    }
+
+    pub fn is_touching(&self, surface: Surface) -> bool {
+        self.normal(surface).is_some()
+    }
+}
+
+/// Find every surface within `tolerance` of a shape, without overlapping it: the floor it's
+/// standing on, walls it's pressed against, and so on.  Arguments are the same as for
+/// `overlapping`, plus the tile colliders, so floors made of lots of tiles count as one surface.
+pub fn contacts<'a, F>(place: &Place, tile_colliders: &TileColliders, actors: &Blockmap, shape_of: F, shape: &Shape, layers: Layers, ignore: Option<ShapeId>, tolerance: WorldUnit) -> Contacts
+    where F: Fn(ShapeId) -> Option<&'a Shape>
+{
+    let mut touches = ArrayVec::new();
+    let bbox = shape.bbox().inflate(tolerance, tolerance);
+    let center = bbox.center();
+    if layers.intersects(Layers::TERRAIN) {
+        tile_colliders.each_touching(place, tiles_touching(bbox), |collider| {
+            if let Some((gap, normal)) = shape.touching_box(collider, tolerance) {
+                // Colliders are only ever made of one material, so any tile in it will do
+                let tile = tile_nearest(collider, center);
+                let material = place.tile_at(tile).material;
+                let _ = touches.try_push(Touch{ target: Target::Tile(tile), normal, gap, material });
+            }
+        });
+    }
+    for id in actors.neighbors_in(&bbox, layers).iter() {
+        if Some(id) == ignore {
+            continue;
+        }
+        let touched = match shape_of(id) {
+            Some(other) => shape.touching(other, tolerance),
+            None => None,
+        };
+        if let Some((gap, normal)) = touched {
+            let material = actors.material(id).unwrap_or_default();
+            if touches.try_push(Touch{ target: Target::Shape(id), normal, gap, material }).is_err() {
+                break;
+            }
+        }
+    }
+    Contacts{ touches }
+}
+
+/// The tile within a collider that's closest to a point.
+fn tile_nearest(collider: &Rect, point: Point) -> TilePoint {
+    // Colliders' far edges belong to the next tile over, so stop just short of them
+    let tiny = Fixed::from_bits(1);
+    world_to_tile(Point::new(
+        clamp(point.x, collider.min_x(), collider.max_x() - tiny),
+        clamp(point.y, collider.min_y(), collider.max_y() - tiny),
+    ))
+}
@@ -0,0 +1,152 @@
+/// Rays, for asking what's between two points: line of sight, lasers, probing for the ground.
+
+use crate::data::places::Place;
+use crate::geom::{Point, Rect, Vector, VectorExt, WorldUnit, TILE_SIZE, tile_to_world_rect, vec2, world_to_tile};
+use super::blockmap::{Blockmap, ShapeId};
+use super::layers::Layers;
+use super::Target;
+use super::shapes::Shape;
+
+/// A ray from one point to another.  (So, really a line segment, but nobody calls it that.)
+#[derive(Clone, Copy, Debug)]
+pub struct Ray {
+    pub start: Point,
+    pub end: Point,
+    /// Unit vector pointing from start to end, or zero if they're the same point
+    pub direction: Vector,
+    pub length: WorldUnit,
+}
+
+impl Ray {
+    pub fn new(start: Point, end: Point) -> Ray {
+        let offset = end - start;
+        let length = offset.length();
+        let direction = if length == 0 {
+            Vector::zero()
+        }
+        else {
+            offset / length
+        };
+        Ray{ start, end, direction, length }
+    }
+
+    /// The point some distance along the ray.
+    pub fn point_at(&self, distance: WorldUnit) -> Point {
+        if self.length == 0 {
+            return self.start;
+        }
+        // Scale the whole offset rather than the direction, which has already lost precision
+        self.start + (self.end - self.start) * distance / self.length
+    }
+
+    pub fn bbox(&self) -> Rect {
+        Rect::from_points(&[self.start, self.end])
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct RayHit {
+    pub point: Point,
+    /// How far along the ray the hit is
+    pub distance: WorldUnit,
+    /// Normal of the surface that was hit, facing back towards the ray.  If the ray started inside
+    /// something, this is just the ray's direction reversed.
+    pub normal: Vector,
+    pub target: Target,
+}
+
+/// Find the first solid tile along a ray, by stepping from tile to tile (a DDA).
+pub fn raycast_tiles(place: &Place, ray: &Ray) -> Option<RayHit> {
+    let mut tile = world_to_tile(ray.start);
+    if place.tile_at(tile).solid {
+        return Some(RayHit{ point: ray.start, distance: 0.into(), normal: -ray.direction, target: Target::Tile(tile) });
+    }
+    if ray.length == 0 {
+        return None;
+    }
+
+    // For each axis, how far along the ray the next tile boundary is, and how far apart the
+    // boundaries are.  Multiply by the length before dividing by the offset, rather than dividing
+    // by the direction, which has already been rounded
+    let tile_size = WorldUnit::from(TILE_SIZE);
+    let bounds = tile_to_world_rect(tile);
+    let offset = ray.end - ray.start;
+    let (step_x, mut next_x, delta_x) = if offset.x > 0 {
+        (1, (bounds.max_x() - ray.start.x) * ray.length / offset.x, tile_size * ray.length / offset.x)
+    }
+    else if offset.x < 0 {
+        (-1, (bounds.min_x() - ray.start.x) * ray.length / offset.x, tile_size * ray.length / -offset.x)
+    }
+    else {
+        (0, WorldUnit::max_value(), WorldUnit::max_value())
+    };
+    let (step_y, mut next_y, delta_y) = if offset.y > 0 {
+        (1, (bounds.max_y() - ray.start.y) * ray.length / offset.y, tile_size * ray.length / offset.y)
+    }
+    else if offset.y < 0 {
+        (-1, (bounds.min_y() - ray.start.y) * ray.length / offset.y, tile_size * ray.length / -offset.y)
+    }
+    else {
+        (0, WorldUnit::max_value(), WorldUnit::max_value())
+    };
+
+    loop {
+        // Cross whichever boundary comes first.  On an exact corner, go vertically first
+        let distance;
+        let normal;
+        if next_x < next_y {
+            tile.x += step_x;
+            distance = next_x;
+            normal = vec2(-step_x as i16, 0);
+            next_x = next_x.saturating_add(delta_x);
+        }
+        else {
+            tile.y += step_y;
+            distance = next_y;
+            normal = vec2(0, -step_y as i16);
+            next_y = next_y.saturating_add(delta_y);
+        }
+
+        if distance > ray.length {
+            return None;
+        }
+        if place.tile_at(tile).solid {
+            return Some(RayHit{ point: ray.point_at(distance), distance, normal, target: Target::Tile(tile) });
+        }
+    }
+}
+
+/// Can you see from one point to another without a solid tile in the way?  Actors don't block
+/// sight.
+pub fn line_of_sight(place: &Place, from: Point, to: Point) -> bool {
+    raycast_tiles(place, &Ray::new(from, to)).is_none()
+}
+
+/// Find the first thing along a ray, whether it's a tile or one of the actors in `actors`.
+/// `shape_of` looks up the shape for an id, `layers` is what the ray can hit (tiles are
+/// `TERRAIN`), and `ignore` is usually whoever's casting the ray.
+pub fn raycast<'a, F>(place: &Place, actors: &Blockmap, shape_of: F, ray: &Ray, layers: Layers, ignore: Option<ShapeId>) -> Option<RayHit>
+    where F: Fn(ShapeId) -> Option<&'a Shape>
+{
+    let mut best = if layers.intersects(Layers::TERRAIN) {
+        raycast_tiles(place, ray)
+    }
+    else {
+        None
+    };
+    for id in actors.neighbors_in(&ray.bbox(), layers).iter() {
+        if Some(id) == ignore {
+            continue;
+        }
+        let shape = match shape_of(id) {
+            Some(shape) => shape,
+            None => continue,
+        };
+        if let Some((distance, normal)) = shape.raycast(ray) {
+            if best.as_ref().map_or(true, |hit| distance < hit.distance) {
+                best = Some(RayHit{ point: ray.point_at(distance), distance, normal, target: Target::Shape(id) });
+            }
+        }
+    }
+    best
+}
@@ -1,7 +1,8 @@
 use arrayvec::ArrayVec;
 
 use crate::fixed::Fixed;
-use crate::geom::{Point, Rect, Vector, VectorExt, WorldUnit, vec2};
+use crate::geom::{Point, Rect, RectExt, Size, Vector, VectorExt, WorldUnit, vec2};
+use super::raycast::Ray;
 
 /// Allowed rounding error when comparing whether two shapes are overlapping.
 /// If they overlap by only this amount, they'll be considered touching.
@@ -17,8 +18,9 @@ pub fn fudge_to_zero(n: Fixed) -> Fixed {
     }
 }
 
-/// Type of contact to expect from one body moving towards another.
-#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+/// Type of contact to expect from one body moving towards another.  Ordered from most to least
+/// severe, which is how ties get broken when sorting collisions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
 pub enum Contact {
     /// This only happens when the two bodies ALREADY overlap.
     Overlap,
@@ -35,20 +37,171 @@ local YPOS = Vector(0, 1)
 local YNEG = Vector(0, -1)
 */
 
-struct Shape {
-  offset_x: Fixed,
-  offset_y: Fixed,
+/// Anything that can collide.
+pub enum Shape {
+    Polygon(Polygon),
+    Circle(Circle),
+    Capsule(Capsule),
+    Multi(MultiShape),
 }
 
 impl Shape {
-  fn new() -> Shape {
-    // XXX
-    // self.blockmaps = setmetatable({}, {__mode = 'k'})
-    Shape{
-      offset_x: 0.into(),
-      offset_y: 0.into(),
+    pub fn bbox(&self) -> Rect {
+        match self {
+            Shape::Polygon(polygon) => polygon.bbox,
+            Shape::Circle(circle) => circle.bbox,
+            Shape::Capsule(capsule) => capsule.bbox,
+            Shape::Multi(multi) => multi.bbox,
+        }
+    }
+
+    /// Extend our bbox along a movement vector (to enclose all space we might cross along the
+    /// way)
+    pub fn extended_bbox(&self, d: Vector) -> Rect {
+        extend_rect(&self.bbox(), d)
+    }
+
+    pub fn move_by(&mut self, d: Vector) {
+        match self {
+            Shape::Polygon(polygon) => polygon.move_by(d),
+            Shape::Circle(circle) => circle.move_by(d),
+            Shape::Capsule(capsule) => capsule.move_by(d),
+            Shape::Multi(multi) => multi.move_by(d),
+        }
+    }
+
+    /// A mirror image of this shape, flipped across the vertical line x = axis.
+    pub fn flipped_x(&self, axis: WorldUnit) -> Shape {
+        match self {
+            Shape::Polygon(polygon) => Shape::Polygon(polygon.flipped_x(axis)),
+            Shape::Circle(circle) => Shape::Circle(circle.flipped_x(axis)),
+            Shape::Capsule(capsule) => Shape::Capsule(capsule.flipped_x(axis)),
+            Shape::Multi(multi) => Shape::Multi(multi.flipped_x(axis)),
+        }
+    }
+
+    /// Does this shape overlap another?  Merely touching doesn't count.
+    pub fn overlaps(&self, other: &Shape) -> bool {
+        let theirs = other.pieces();
+        self.pieces().iter().any(|&ours| theirs.iter().any(|&theirs| convex_overlaps(ours, theirs)))
+    }
+
+    /// Does this shape overlap a box?  Merely touching doesn't count.
+    pub fn overlaps_box(&self, other: &Rect) -> bool {
+        self.pieces().iter().any(|&ours| match ours.as_box() {
+            Some(our_box) => box_overlaps(our_box, other),
+            None => convex_overlaps(ours, &Polygon::from_rect(*other)),
+        })
+    }
+
+    /// Is this point inside the shape?  Points right on the edge aren't.
+    pub fn contains_point(&self, point: Point) -> bool {
+        // A point is just a very small circle
+        let point = Circle::new(point, 0.into());
+        self.pieces().iter().any(|&ours| convex_overlaps(ours, &point))
+    }
+
+    /// If this shape is within `tolerance` of another one without overlapping it, how big the gap
+    /// is, and the normal of their surface, pointing back at us.  Good for finding out what we're
+    /// resting on or brushing against, which `slide_towards` can't tell us.
+    pub fn touching(&self, other: &Shape, tolerance: WorldUnit) -> Option<(WorldUnit, Vector)> {
+        let theirs = other.pieces();
+        let mut best = None;
+        for &ours in self.pieces().iter() {
+            for &theirs in theirs.iter() {
+                keep_nearest(&mut best, convex_touching(ours, theirs, tolerance));
+            }
+        }
+        best
+    }
+
+    /// Same as `touching`, for a box.
+    pub fn touching_box(&self, other: &Rect, tolerance: WorldUnit) -> Option<(WorldUnit, Vector)> {
+        let theirs = Polygon::from_rect(*other);
+        let mut best = None;
+        for &ours in self.pieces().iter() {
+            keep_nearest(&mut best, convex_touching(ours, &theirs, tolerance));
+        }
+        best
+    }
+
+    /// If this shape overlaps another, the shortest movement that would push it back out.  If
+    /// several of our pieces overlap, this is the biggest push any of them needs.
+    pub fn penetration(&self, other: &Shape) -> Option<Vector> {
+        let theirs = other.pieces();
+        let mut best = None;
+        for &ours in self.pieces().iter() {
+            for &theirs in theirs.iter() {
+                keep_deepest(&mut best, convex_penetration(ours, theirs));
+            }
+        }
+        best
+    }
+
+    /// Same as `penetration`, for a box.
+    pub fn penetration_box(&self, other: &Rect) -> Option<Vector> {
+        let theirs = Polygon::from_rect(*other);
+        let mut best = None;
+        for &ours in self.pieces().iter() {
+            keep_deepest(&mut best, convex_penetration(ours, &theirs));
+        }
+        best
+    }
+
+    /// Where a ray first hits this shape, as a distance along the ray and the normal of the
+    /// surface it hits.  A ray that starts inside hits immediately.
+    pub fn raycast(&self, ray: &Ray) -> Option<(WorldUnit, Vector)> {
+        let mut best = None;
+        for &piece in self.pieces().iter() {
+            keep_nearest(&mut best, convex_raycast(piece, ray));
+        }
+        best
+    }
+
+    /// The convex pieces this shape is made of; anything but a MultiShape is just itself.
+    fn pieces(&self) -> ArrayVec<[&dyn Convex; MAX_SUBSHAPES]> {
+        let mut pieces = ArrayVec::<[&dyn Convex; MAX_SUBSHAPES]>::new();
+        match self {
+            Shape::Polygon(polygon) => pieces.push(polygon),
+            Shape::Circle(circle) => pieces.push(circle),
+            Shape::Capsule(capsule) => pieces.push(capsule),
+            Shape::Multi(multi) => {
+                for subshape in multi.subshapes.iter() {
+                    pieces.push(subshape);
+                }
+            }
+        }
+        pieces
+    }
+
+    /// See `Polygon::slide_towards`.  Compound shapes collide piece by piece, and the results are
+    /// merged into whichever collision happens first.
+    pub fn slide_towards(&self, other: &Shape, movement: Vector) -> Option<Collision> {
+        if let (Shape::Polygon(ours), Shape::Polygon(theirs)) = (self, other) {
+            return ours.slide_towards(theirs, movement);
+        }
+
+        let mut ret = None;
+        for &ours in self.pieces().iter() {
+            for &theirs in other.pieces().iter() {
+                merge_collision(&mut ret, convex_slide_towards(ours, theirs, movement));
+            }
+        }
+        ret
+    }
+
+    /// See `Polygon::slide_towards_box`.
+    pub fn slide_towards_box(&self, other: &Rect, movement: Vector) -> Option<Collision> {
+        if let Shape::Polygon(ours) = self {
+            return ours.slide_towards_box(other, movement);
+        }
+
+        let mut ret = None;
+        for &ours in self.pieces().iter() {
+            merge_collision(&mut ret, convex_slide_towards_box(ours, other, movement));
+        }
+        ret
     }
-  }
 
   // TODO debug?  ha.
 
@@ -70,11 +223,89 @@ end
 
 }
 
-/* XXX?
-function Shape:flipx(axis)
-    error("flipx not implemented")
-end
+/// Most pieces a MultiShape can be made of.
+pub const MAX_SUBSHAPES: usize = 4;
+
+/// A shape made out of several convex polygons, which together can be concave.
+pub struct MultiShape {
+    subshapes: ArrayVec<[Polygon; MAX_SUBSHAPES]>,
+    bbox: Rect,
+}
 
+impl MultiShape {
+    /// Panics if there are no subshapes, or more than `MAX_SUBSHAPES`.
+    pub fn new<I: IntoIterator<Item=Polygon>>(subshapes: I) -> MultiShape {
+        let subshapes: ArrayVec<[Polygon; MAX_SUBSHAPES]> = subshapes.into_iter().collect();
+        assert!(! subshapes.is_empty(), "MultiShape needs at least one subshape");
+        let mut bbox = subshapes[0].bbox;
+        for subshape in subshapes.iter().skip(1) {
+            bbox = bbox.union(&subshape.bbox);
+        }
+        MultiShape{ subshapes, bbox }
+    }
+
+    pub fn move_by(&mut self, d: Vector) {
+        for subshape in self.subshapes.iter_mut() {
+            subshape.move_by(d);
+        }
+        self.bbox.origin += d;
+    }
+
+    pub fn flipped_x(&self, axis: WorldUnit) -> MultiShape {
+        MultiShape::new(self.subshapes.iter().map(|subshape| subshape.flipped_x(axis)))
+    }
+}
+
+/// Combine the result of colliding with one subshape into the running result for a whole
+/// compound shape.  The earliest collision wins; ties pool their normals, keeping the best one on
+/// each side, same as `Sweep` does for a single shape.
+fn merge_collision(ret: &mut Option<Collision>, collision: Option<Collision>) {
+    let collision = match collision {
+        // Do nothing
+        None => return,
+        Some(collision) => collision,
+    };
+    let current = match ret {
+        None => {
+            // First result; just accept it
+            *ret = Some(collision);
+            return;
+        }
+        Some(current) => current,
+    };
+
+    // Need to combine
+    if collision.amount < current.amount {
+        *current = collision;
+    }
+    else if collision.amount == current.amount {
+        current.touchdist = current.touchdist.min(collision.touchdist);
+        if current.touchtype == Contact::Touch {
+            current.touchtype = collision.touchtype;
+        }
+        // The same surface often turns up in more than one piece, like the tops of two tiles in a
+        // floor, but its normal might come out a different length each time, and its dot a hair
+        // different from rounding.  Keep whichever copy came first, so it only counts once
+        if collision.left_normal_dot > current.left_normal_dot && ! same_direction(current.left_normal, collision.left_normal) {
+            current.left_normal_dot = collision.left_normal_dot;
+            current.left_normal = collision.left_normal;
+        }
+        if collision.right_normal_dot > current.right_normal_dot && ! same_direction(current.right_normal, collision.right_normal) {
+            current.right_normal_dot = collision.right_normal_dot;
+            current.right_normal = collision.right_normal;
+        }
+    }
+}
+
+/// Do two normals point exactly the same way, regardless of length?
+fn same_direction(a: Option<Vector>, b: Option<Vector>) -> bool {
+    match (a, b) {
+        (Some(a), Some(b)) => a.x * b.y == a.y * b.x && a.dot(b) > 0,
+        _ => false,
+    }
+}
+
+/* XXX?
 function Shape:move(dx, dy)
     error("move not implemented")
 end
@@ -92,13 +323,72 @@ function Shape:normals()
 end
 */
 
+/// A convex shape: the convex hull of some points, fattened by a radius.  A polygon has no
+/// radius, a circle is a single point, and a capsule is two.
+pub trait Convex {
+    fn bbox(&self) -> Rect;
+    fn vertices(&self) -> &[Point];
+    fn radius(&self) -> Fixed {
+        0.into()
+    }
+    fn has_horizontal_normal(&self) -> bool;
+    fn has_vertical_normal(&self) -> bool;
+    /// Normals of any edges that aren't axis-aligned.
+    fn other_normals(&self) -> &[Vector];
+    /// If this is an axis-aligned box, its bbox, so collision can take a shortcut.
+    fn as_box(&self) -> Option<&Rect> {
+        None
+    }
+
+    /// Project onto an axis, which doesn't need to be normalized.  Returns the min and max dot
+    /// products, and the points that produced them.
+    fn project_onto_axis(&self, axis: Vector) -> (Fixed, Fixed, Point, Point) {
+        let points = self.vertices();
+        let mut minpt = points[0];
+        let mut maxpt = minpt;
+        let mut min = axis.dot(minpt.to_vector());
+        let mut max = min;
+        for &pt in points.iter().skip(1) {
+            let dot = axis.dot(pt.to_vector());
+            if dot < min {
+                min = dot;
+                minpt = pt;
+            }
+            else if dot > max {
+                max = dot;
+                maxpt = pt;
+            }
+        }
+
+        let radius = self.radius();
+        // A zero axis projects everything to zero, and there's no direction to fatten along
+        let length = axis.length();
+        if radius > 0 && length > 0 {
+            // Everything is fattened by the radius, which is a bit longer when measured along an
+            // unnormalized axis
+            let offset = axis * radius / length;
+            min -= radius * length;
+            max += radius * length;
+            minpt -= offset;
+            maxpt += offset;
+        }
+        return (min, max, minpt, maxpt);
+    }
+}
+
+/// Most axes two shapes can need checking.  The worst case is a polygon against a capsule: the
+/// two axis-aligned ones, every polygon edge plus the capsule's spine, and an axis between each
+/// polygon corner and each end of the capsule, for 2 + 8 + 1 + 16 = 27.  arrayvec only does
+/// certain sizes, so round up.
+const MAX_AXES: usize = 32;
+
 #[inline]
-fn axial_projections(a: &Polygon, b: &Polygon) -> ArrayVec<[(Vector, Fixed, Vector); 4]> {
+fn axial_projections<A: Convex + ?Sized, B: Convex + ?Sized>(a: &A, b: &B, movement: Vector) -> ArrayVec<[(Vector, Fixed, Vector); MAX_AXES]> {
     let mut projections = ArrayVec::new();
 
-    if a.has_horizontal_normal || b.has_horizontal_normal {
-        let (min1, max1) = (a.bbox.min_x(), a.bbox.max_x());
-        let (min2, max2) = (b.bbox.min_x(), b.bbox.max_x());
+    if a.has_horizontal_normal() || b.has_horizontal_normal() {
+        let (min1, max1) = (a.bbox().min_x(), a.bbox().max_x());
+        let (min2, max2) = (b.bbox().min_x(), b.bbox().max_x());
         let dist;
         let sep;
         let mut fullaxis = vec2(1, 0);
@@ -121,9 +411,9 @@ fn axial_projections(a: &Polygon, b: &Polygon) -> ArrayVec<[(Vector, Fixed, Vect
     
     }
 
-    if a.has_vertical_normal || b.has_vertical_normal {
-        let (min1, max1) = (a.bbox.min_y(), a.bbox.max_y());
-        let (min2, max2) = (b.bbox.min_y(), b.bbox.max_y());
+    if a.has_vertical_normal() || b.has_vertical_normal() {
+        let (min1, max1) = (a.bbox().min_y(), a.bbox().max_y());
+        let (min2, max2) = (b.bbox().min_y(), b.bbox().max_y());
         let dist;
         let sep;
         let mut fullaxis = vec2(0, 1);
@@ -145,45 +435,110 @@ fn axial_projections(a: &Polygon, b: &Polygon) -> ArrayVec<[(Vector, Fixed, Vect
         projections.push((fullaxis, dist, sep));
     }
 
-    for &(mut fullaxis) in a.other_normals.iter().chain(b.other_normals.iter()) {
-        if fullaxis == Vector::zero() {
+    for &fullaxis in a.other_normals().iter().chain(b.other_normals().iter()) {
+        // Same as in corner_axis: an axis too short to measure can't be normalized
+        if fullaxis.square_length() == 0 {
             continue;
         }
 
-        let (min1, max1, minpt1, maxpt1) = a.project_onto_axis(fullaxis);
-        let (min2, max2, minpt2, maxpt2) = b.project_onto_axis(fullaxis);
-        let dist;
-        let sep;
-        if min1 < min2 {
-            // 1 appears first, so take the distance from 1 to 2
-            // Ignore extremely tiny overlaps, which are likely precision errors
-            dist = fudge_to_zero(min2 - max1);
-            sep = minpt2 - maxpt1;
-        }
-        else {
-            // Other way around
-            dist = fudge_to_zero(min1 - max2);
-            // Note that sep is always the vector from us to them
-            sep = maxpt2 - minpt1;
-            // Likewise, flip the axis so it points towards them
-            fullaxis = -fullaxis;
+        projections.push(project_both(a, b, fullaxis));
+    }
+
+    // Rounded shapes have a curved edge at every corner, and the axis that matters there is the
+    // one between the two corners at the moment they touch
+    let radius = a.radius() + b.radius();
+    if radius > 0 {
+        for &pt1 in a.vertices() {
+            for &pt2 in b.vertices() {
+                if let Some(fullaxis) = corner_axis(pt1 - pt2, movement, radius) {
+                    projections.push(project_both(a, b, fullaxis));
+                }
+            }
         }
     }
 
     return projections;
 }
 
+#[inline]
+fn project_both<A: Convex + ?Sized, B: Convex + ?Sized>(a: &A, b: &B, mut fullaxis: Vector) -> (Vector, Fixed, Vector) {
+    let (min1, max1, minpt1, maxpt1) = a.project_onto_axis(fullaxis);
+    let (min2, max2, minpt2, maxpt2) = b.project_onto_axis(fullaxis);
+    let dist;
+    let sep;
+    if min1 < min2 {
+        // 1 appears first, so take the distance from 1 to 2
+        // Ignore extremely tiny overlaps, which are likely precision errors
+        dist = fudge_to_zero(min2 - max1);
+        sep = minpt2 - maxpt1;
+    }
+    else {
+        // Other way around
+        dist = fudge_to_zero(min1 - max2);
+        // Note that sep is always the vector from us to them
+        sep = maxpt2 - minpt1;
+        // Likewise, flip the axis so it points towards them
+        fullaxis = -fullaxis;
+    }
+
+    (fullaxis, dist, sep)
+}
+
+/// Direction from their corner to ours at the moment they first come within `radius` of each
+/// other, if ours starts `offset` away and moves by `movement`.  If they never get that close,
+/// this is the direction at their closest approach instead, which is just as good for showing
+/// that they miss.
+fn corner_axis(offset: Vector, movement: Vector, radius: Fixed) -> Option<Vector> {
+    let axis;
+    let length = movement.length();
+    if length == 0 {
+        // Either we're not moving, or we're moving so little (under about 1/16px) that the
+        // length rounds to zero and there's no telling which way; same thing, really
+        axis = offset;
+    }
+    else {
+        // Work along the direction of movement, which keeps the numbers small enough not to
+        // overflow: solve |offset + s * direction| = radius for the smaller s
+        let direction = movement / length;
+        let along = offset.dot(direction);
+        let across = offset.dot(direction.perpendicular());
+        let discriminant = radius * radius - across * across;
+        let s = if discriminant < 0 {
+            -along
+        }
+        else {
+            -along - discriminant.sqrt()
+        };
+        axis = offset + direction * s;
+    }
+
+    // An axis too short to have a length can't be normalized, so it's no use to anyone
+    if axis.square_length() == 0 {
+        None
+    }
+    else {
+        Some(axis)
+    }
+}
+
 // TODO gotta merge all these
 
-/// An arbitrary (CONVEX) polygon
+/// Most vertices a Polygon can have.
+pub const MAX_POLYGON_POINTS: usize = 8;
+
+/// An arbitrary (CONVEX) polygon, with anywhere from 3 to `MAX_POLYGON_POINTS` vertices
 pub struct Polygon {
     // TODO i would love to not need points for an AABB, but i don't pass these around by value so
     // maybe it doesn't matter
-    points: [Point; 4],
+    points: ArrayVec<[Point; MAX_POLYGON_POINTS]>,
     bbox: Rect,
     has_vertical_normal: bool,
     has_horizontal_normal: bool,
-    other_normals: ArrayVec<[Vector; 2]>,
+    /// Normals of all the edges that aren't axis-aligned, minus any that are parallel to each
+    /// other, since they'd just be the same axis twice
+    other_normals: ArrayVec<[Vector; MAX_POLYGON_POINTS]>,
+    /// True if this is an axis-aligned box, which gets a much faster slide_towards
+    is_box: bool,
 }
 
 
@@ -202,15 +557,6 @@ function Polygon:to_coords()
     return coords
 end
 
-function Polygon:flipx(axis)
-    local reverse_coords = {}
-    for n, point in ipairs(self.points) do
-        reverse_coords[#self.points * 2 - (n * 2 - 1)] = axis * 2 - point.x
-        reverse_coords[#self.points * 2 - (n * 2 - 2)] = point.y
-    end
-    return Polygon(unpack(reverse_coords))
-end
-
 function Polygon:_generate_normals()
     self._normals = {}
     local prev_point = self.points[#self.points]
@@ -270,66 +616,6 @@ end
 function Polygon:normals()
     return self._normals
 end
-
--- TODO implement this for other types
-function Polygon:intersection_with_ray(start, direction)
-    local perp = direction:perpendicular()
-    -- TODO could save a little effort by passing these in too, maybe
-    local startdot = start * direction
-    local startperpdot = start * perp
-    local pt0 = self.points[#self.points]
-    local dot0 = pt0 * perp
-    local minpt = nil
-    local mindot = math.huge
-    for _, point in ipairs(self.points) do
-        local pt, dot
-        local pt1 = point
-        local dot1 = pt1 * perp
-        if dot0 == dot1 then
-            -- This edge is parallel to the ray.  If it's also collinear to the
-            -- ray, figure out where it hits
-            if dot0 == startperpdot then
-                local startdot = start * direction
-                local ldot0 = pt0 * direction
-                local ldot1 = pt1 * direction
-                if (ldot0 <= startdot and startdot <= ldot1) or
-                    (ldot1 <= startdot and startdot <= ldot0)
-                then
-                    -- Ray starts somewhere inside this line, so the start
-                    -- point must be the closest point
-                    return start, 0
-                elseif ldot0 < startdot and ldot1 < startdot then
-                    -- Ray starts after this line and misses it entirely;
-                    -- do nothing
-                elseif ldot0 < ldot1 then
-                    pt = pt0
-                    dot = ldot0
-                else
-                    pt = pt1
-                    dot = ldot1
-                end
-            end
-        elseif (dot0 <= startperpdot and startperpdot <= dot1) or
-            (dot1 <= startperpdot and startperpdot <= dot0)
-        then
-            pt = pt0 + (pt1 - pt0) * (startperpdot - dot0) / (dot1 - dot0)
-            dot = pt * direction
-        end
-        if pt then
-            if dot >= startdot and dot < mindot then
-                mindot = dot
-                minpt = pt
-            end
-        end
-        pt0 = pt1
-        dot0 = dot1
-    end
-    -- TODO i feel like this doesn't really do the right thing if the start
-    -- point is inside the poly?  should it, i dunno, return the point instead,
-    -- since that's the first point where the ray intersects the polygon itself
-    -- rather than an edge?
-    return minpt, mindot
-end
 */
 
 
@@ -338,7 +624,7 @@ pub enum CollisionNormal {
     Constrained(Vector, Fixed),
     Free,
 }
-#[derive(Debug)]
+#[derive(Debug, PartialEq)]
 pub struct Collision {
     pub movement: Vector,
     pub amount: WorldUnit,
@@ -368,39 +654,57 @@ impl Collision {
 }
 
 impl Polygon {
-    pub fn new(points: [Point; 4]) -> Polygon {
+    /// Build a polygon from its vertices, in order.  Panics if there are fewer than 3 or more
+    /// than `MAX_POLYGON_POINTS`.
+    pub fn new(points: &[Point]) -> Polygon {
+        assert!(points.len() >= 3 && points.len() <= MAX_POLYGON_POINTS, "polygon needs 3 to {} points", MAX_POLYGON_POINTS);
+        let points: ArrayVec<[Point; MAX_POLYGON_POINTS]> = points.iter().cloned().collect();
         let bbox = Rect::from_points(&points);
         let mut has_horizontal_normal = false;
         let mut has_vertical_normal = false;
-        let mut other_normals = ArrayVec::new();
-        for &(i, j) in &[(0, 1), (1, 2), (2, 3), (3, 0)] {
-            let edge = points[j] - points[i];
-            if edge.x == 0 {
+        let mut other_normals = ArrayVec::<[Vector; MAX_POLYGON_POINTS]>::new();
+        let mut prev_point = points[points.len() - 1];
+        for &point in points.iter() {
+            let edge = point - prev_point;
+            prev_point = point;
+            if edge == Vector::zero() {
+                // Ignore zero vectors (where did you even come from)
+            }
+            else if edge.x == 0 {
                 has_horizontal_normal = true;
             }
             else if edge.y == 0 {
                 has_vertical_normal = true;
             }
             else {
-                other_normals.push(edge.perpendicular());
+                // Opposite sides of a hexagon and the like are parallel, and projecting onto the
+                // same axis twice is a waste of time
+                let normal = edge.perpendicular();
+                if ! other_normals.iter().any(|other| other.x * normal.y == other.y * normal.x) {
+                    other_normals.push(normal);
+                }
             }
         }
+        // A convex shape whose edges are all axis-aligned has to be a box
+        let is_box = other_normals.is_empty() && has_horizontal_normal && has_vertical_normal;
         Polygon{
             points,
             bbox,
             has_horizontal_normal,
             has_vertical_normal,
             other_normals,
+            is_box,
         }
     }
 
     pub fn from_rect(rect: Rect) -> Polygon {
         Polygon{
-            points: [rect.origin, rect.top_right(), rect.bottom_right(), rect.bottom_left()],
+            points: [rect.origin, rect.top_right(), rect.bottom_right(), rect.bottom_left()].iter().cloned().collect(),
             bbox: rect,
             has_horizontal_normal: true,
             has_vertical_normal: true,
             other_normals: ArrayVec::new(),
+            is_box: true,
         }
     }
 
@@ -414,48 +718,23 @@ impl Polygon {
     /// Extend a bbox along a movement vector (to enclose all space it might cross
     /// along the way)
     pub fn extended_bbox(&self, d: Vector) -> Rect {
-        let mut bbox = self.bbox.clone();
-
-        if d.x < 0 {
-            bbox.origin.x += d.x;
-            bbox.size.width -= d.x;
-        }
-        else if d.x > 0 {
-            bbox.size.width += d.x;
-        }
-
-        if d.y < 0 {
-            bbox.origin.y += d.y;
-            bbox.size.height -= d.y;
-        }
-        else if d.y > 0 {
-            bbox.size.height += d.y;
-        }
-
-        bbox
+        extend_rect(&self.bbox, d)
     }
 
     pub fn center(&self) -> Point {
         self.bbox.origin.add_size(&(self.bbox.size / Fixed::promote(2)))
     }
 
-    pub fn project_onto_axis(&self, axis: Vector) -> (Fixed, Fixed, Point, Point) {
-        let mut minpt = self.points[0];
-        let mut maxpt = minpt;
-        let mut min = axis.dot(minpt.to_vector());
-        let mut max = min;
-        for &pt in self.points.iter().skip(1) {
-            let dot = axis.dot(pt.to_vector());
-            if dot < min {
-                min = dot;
-                minpt = pt;
-            }
-            else if dot > max {
-                max = dot;
-                maxpt = pt;
-            }
+    /// A mirror image of this polygon, flipped across the vertical line x = axis.
+    pub fn flipped_x(&self, axis: WorldUnit) -> Polygon {
+        if self.is_box {
+            return Polygon::from_rect(flip_rect_x(&self.bbox, axis));
         }
-        return (min, max, minpt, maxpt);
+        // Mirroring reverses the winding, so go backwards to keep it the same
+        let points: ArrayVec<[Point; MAX_POLYGON_POINTS]> = self.points.iter().rev()
+            .map(|&point| flip_point_x(point, axis))
+            .collect();
+        Polygon::new(&points)
     }
 
     /// If this shape were to move by a given distance, would it collide with the given other
@@ -463,165 +742,671 @@ impl Polygon {
     ///
     /// Note that a Collision is returned even if the two shapes would exactly touch without
     /// colliding, or would exactly slide against each other.
-    // FIXME couldn't there be a much simpler version of this for two AABBs?
     pub fn slide_towards(&self, other: &Polygon, movement: Vector) -> Option<Collision> {
-        use crate::debug::StopwatchGuard;
-        //let _sw = StopwatchGuard::with_message("slide_towards");
-        // We cannot possibly collide if the bboxes don't overlap
-        let our_bbox = self.extended_bbox(movement);
-        if ! our_bbox.intersects(&other.bbox) {
+        convex_slide_towards(self, other, movement)
+    }
+
+    /// Same as `slide_towards`, but against a bare box, which saves building a whole Polygon for
+    /// it when we're a box ourselves.
+    pub fn slide_towards_box(&self, other: &Rect, movement: Vector) -> Option<Collision> {
+        convex_slide_towards_box(self, other, movement)
+    }
+}
+
+impl Convex for Polygon {
+    fn bbox(&self) -> Rect {
+        self.bbox
+    }
+
+    fn vertices(&self) -> &[Point] {
+        &self.points
+    }
+
+    fn has_horizontal_normal(&self) -> bool {
+        self.has_horizontal_normal
+    }
+
+    fn has_vertical_normal(&self) -> bool {
+        self.has_vertical_normal
+    }
+
+    fn other_normals(&self) -> &[Vector] {
+        &self.other_normals
+    }
+
+    fn as_box(&self) -> Option<&Rect> {
+        if self.is_box {
+            Some(&self.bbox)
+        }
+        else {
+            None
+        }
+    }
+}
+
+/// A circle.  Good for projectiles, and anything else that wants to roll over corners.
+pub struct Circle {
+    center: [Point; 1],
+    radius: Fixed,
+    bbox: Rect,
+}
+
+impl Circle {
+    pub fn new(center: Point, radius: Fixed) -> Circle {
+        Circle{
+            center: [center],
+            radius,
+            bbox: Rect::new(center, Size::zero()).inflate(radius, radius),
+        }
+    }
+
+    pub fn center(&self) -> Point {
+        self.center[0]
+    }
+
+    pub fn move_by(&mut self, d: Vector) {
+        self.center[0] += d;
+        self.bbox.origin += d;
+    }
+
+    pub fn flipped_x(&self, axis: WorldUnit) -> Circle {
+        Circle::new(flip_point_x(self.center[0], axis), self.radius)
+    }
+
+    pub fn slide_towards(&self, other: &Shape, movement: Vector) -> Option<Collision> {
+        let mut ret = None;
+        for &theirs in other.pieces().iter() {
+            merge_collision(&mut ret, convex_slide_towards(self, theirs, movement));
+        }
+        ret
+    }
+}
+
+impl Convex for Circle {
+    fn bbox(&self) -> Rect {
+        self.bbox
+    }
+
+    fn vertices(&self) -> &[Point] {
+        &self.center
+    }
+
+    fn radius(&self) -> Fixed {
+        self.radius
+    }
+
+    fn has_horizontal_normal(&self) -> bool {
+        false
+    }
+
+    fn has_vertical_normal(&self) -> bool {
+        false
+    }
+
+    fn other_normals(&self) -> &[Vector] {
+        &[]
+    }
+}
+
+/// A line segment with a radius, i.e. a rectangle with semicircles on the ends.  Makes for nice
+/// rounded feet that glide over seams and up slopes.
+pub struct Capsule {
+    points: [Point; 2],
+    radius: Fixed,
+    bbox: Rect,
+    has_horizontal_normal: bool,
+    has_vertical_normal: bool,
+    other_normals: ArrayVec<[Vector; 1]>,
+}
+
+impl Capsule {
+    /// A capsule whose spine runs from `start` to `end`.
+    pub fn new(start: Point, end: Point, radius: Fixed) -> Capsule {
+        let edge = end - start;
+        let mut other_normals = ArrayVec::new();
+        if edge.x != 0 && edge.y != 0 {
+            other_normals.push(edge.perpendicular());
+        }
+        Capsule{
+            points: [start, end],
+            radius,
+            bbox: Rect::from_points(&[start, end]).inflate(radius, radius),
+            // A vertical spine has horizontal normals, and vice versa, but a zero-length one is
+            // just a circle and has neither
+            has_horizontal_normal: edge.x == 0 && edge.y != 0,
+            has_vertical_normal: edge.y == 0 && edge.x != 0,
+            other_normals,
+        }
+    }
+
+    pub fn move_by(&mut self, d: Vector) {
+        for point in self.points.iter_mut() {
+            *point += d;
+        }
+        self.bbox.origin += d;
+    }
+
+    pub fn flipped_x(&self, axis: WorldUnit) -> Capsule {
+        Capsule::new(flip_point_x(self.points[0], axis), flip_point_x(self.points[1], axis), self.radius)
+    }
+}
+
+impl Convex for Capsule {
+    fn bbox(&self) -> Rect {
+        self.bbox
+    }
+
+    fn vertices(&self) -> &[Point] {
+        &self.points
+    }
+
+    fn radius(&self) -> Fixed {
+        self.radius
+    }
+
+    fn has_horizontal_normal(&self) -> bool {
+        self.has_horizontal_normal
+    }
+
+    fn has_vertical_normal(&self) -> bool {
+        self.has_vertical_normal
+    }
+
+    fn other_normals(&self) -> &[Vector] {
+        &self.other_normals
+    }
+}
+
+/// If one convex shape were to move by a given distance, would it collide with another?  If no,
+/// returns None.  If yes, returns Some(Collision).
+///
+/// Note that a Collision is returned even if the two shapes would exactly touch without
+/// colliding, or would exactly slide against each other.
+fn convex_slide_towards<A: Convex + ?Sized, B: Convex + ?Sized>(ours: &A, theirs: &B, movement: Vector) -> Option<Collision> {
+    use crate::debug::StopwatchGuard;
+    //let _sw = StopwatchGuard::with_message("slide_towards");
+    if let (Some(our_box), Some(their_box)) = (ours.as_box(), theirs.as_box()) {
+        return box_slide_towards(our_box, their_box, movement);
+    }
+    sat_slide_towards(ours, theirs, movement)
+}
+
+/// The general version of `convex_slide_towards`, which works for any two convex shapes.
+fn sat_slide_towards<A: Convex + ?Sized, B: Convex + ?Sized>(ours: &A, theirs: &B, movement: Vector) -> Option<Collision> {
+    // We cannot possibly collide if the bboxes don't overlap
+    let our_bbox = extend_rect(&ours.bbox(), movement);
+    if ! our_bbox.intersects(&theirs.bbox()) {
+        return None;
+    }
+
+    // Use the separating axis theorem.
+    // 1. Choose a bunch of axes, generally normals of the shapes.
+    // 2. Project both shapes along each axis.
+    // 3. If the projects overlap along ANY axis, the shapes overlap.  Otherwise, they don't.
+    // This code also does a couple other things.
+    // b. It uses the direction of movement as an extra axis, in order to find the minimum
+    //    possible movement between the two shapes.
+    // a. It keeps values around in terms of their original vectors, rather than lengths or
+    //    normalized vectors, to avoid precision loss from taking square roots.
+
+    // Subshapes are handled by Shape::slide_towards, which calls this on each piece
+
+    // FIXME figure out if i actually need the movement as an axis
+    let mut sweep = Sweep::new(movement);
+    for (fullaxis, dist, sep) in axial_projections(ours, theirs, movement) {
+        let axis = fullaxis.normalize();
+        let blocked = sweep.add_axis(
+            fullaxis, dist, sep.dot(fullaxis), movement.dot(fullaxis), movement.dot(axis));
+        if blocked {
             return None;
         }
+    }
+    sweep.finish()
+}
 
-        // Use the separating axis theorem.
-        // 1. Choose a bunch of axes, generally normals of the shapes.
-        // 2. Project both shapes along each axis.
-        // 3. If the projects overlap along ANY axis, the shapes overlap.  Otherwise, they don't.
-        // This code also does a couple other things.
-        // b. It uses the direction of movement as an extra axis, in order to find the minimum
-        //    possible movement between the two shapes.
-        // a. It keeps values around in terms of their original vectors, rather than lengths or
-        //    normalized vectors, to avoid precision loss from taking square roots.
-
-        // XXX no subshapes support
-        /*
-        if other.subshapes then
-            return self:_multi_slide_towards(other, movement)
-        end
-        */
+/// `convex_slide_towards` against a bare box, which saves building a whole Polygon for it when
+/// we're a box ourselves.
+fn convex_slide_towards_box<A: Convex + ?Sized>(ours: &A, theirs: &Rect, movement: Vector) -> Option<Collision> {
+    if let Some(our_box) = ours.as_box() {
+        box_slide_towards(our_box, theirs, movement)
+    }
+    else {
+        convex_slide_towards(ours, &Polygon::from_rect(*theirs), movement)
+    }
+}
 
-        // Mapping of normal vectors (i.e. projection axes) to their normalized
-        // versions (needed for comparing the results of the projection)
-        // FIXME is the move normal actually necessary, or was it just covering up
-        // my bad math before?
-        let movenormal = movement.perpendicular();
-
-        let mut left_max_dot = Fixed::min_value();
-        let mut left_norm = None;
-        let mut right_max_dot = Fixed::min_value();
-        let mut right_norm = None;
-
-        // Project both shapes onto each axis and look for the minimum distance
-        let mut maxamt = Fixed::min_value();
-        let mut maxnumer = 1.into();
-        let mut maxdenom = 1.into();
-        // The semantics of the SAT are that the shapes overlap unless at least one axis
-        // shows they don't, hence the default of Overlap here
-        let mut touchtype = Contact::Overlap;
-        let mut slide_axis = None;
-        // FIXME make this an optimization for boxes only -- or maybe even write a separate
-        // implementation for boxes
-        // FIXME figure out if i actually need the movement as an axis
-        for (mut fullaxis, dist, sep) in axial_projections(self, other) {
-            let axis = fullaxis.normalize();
-
-            // Negative distance means the shapes overlap from this perspective, which is
-            // inconclusive
-            if dist < 0 {
-              continue;
-            }
+/// Do two convex shapes overlap?  Shapes that only touch (give or take PRECISION) don't.
+fn convex_overlaps<A: Convex + ?Sized, B: Convex + ?Sized>(ours: &A, theirs: &B) -> bool {
+    if let (Some(our_box), Some(their_box)) = (ours.as_box(), theirs.as_box()) {
+        return box_overlaps(our_box, their_box);
+    }
+    if ! ours.bbox().intersects(&theirs.bbox()) {
+        return false;
+    }
 
-            // Update touchtype
-            if dist > 0 {
-                touchtype = Contact::Collide;
-            }
-            // XXX excuse me what
-            else if touchtype == Contact::Overlap {
-                touchtype = Contact::Touch;
-            }
+    // Same as a sweep, minus the sweeping: if the shapes are apart along any axis at all, they
+    // don't overlap
+    axial_projections(ours, theirs, Vector::zero()).iter().all(|&(_, dist, _)| dist < 0)
+}
 
-            // This dot product is positive if we're moving closer along this
-            // axis, negative if we're moving away
-            let dot = fudge_to_zero(movement.dot(fullaxis));
+/// The SAT again: the axis with the biggest gap is the one that separates the shapes, so it's the
+/// normal of whatever we're touching.  If they overlap, it's the way out that takes the least
+/// movement, i.e. the minimum translation vector.  Returns the gap (negative for an overlap) and
+/// the unit normal, pointing from them back towards us.
+///
+/// For polygons this can overestimate the gap near corners, which doesn't much matter for a
+/// tolerance check.
+fn convex_separation<A: Convex + ?Sized, B: Convex + ?Sized>(ours: &A, theirs: &B) -> Option<(WorldUnit, Vector)> {
+    let mut best: Option<(WorldUnit, Vector)> = None;
+    for &(fullaxis, dist, _) in axial_projections(ours, theirs, Vector::zero()).iter() {
+        // Axes aren't necessarily unit length, and neither are distances along them
+        let length = fullaxis.length();
+        if length == 0 {
+            // Too tiny to measure along; the other axes will have to do
+            continue;
+        }
+        let gap = dist / length;
+        // On a tie, the later axis wins, which puts the vertical axis ahead of the horizontal
+        // one -- so standing exactly on a ledge's corner counts as standing on it
+        if best.map_or(true, |(best_gap, _)| gap >= best_gap) {
+            best = Some((gap, -fullaxis / length));
+        }
+    }
+    best
+}
 
-            if dot < 0 || (dot == 0 && dist > 0) {
-                // Even if the shapes are already touching, they're not moving
-                // closer together, so they can't possibly collide.  Stop here.
-                // FIXME this means collision detection is not useful for finding touches
-                return None;
-            }
-            else if dist == 0 && dot == 0 {
-                // Zero dot and zero distance mean the movement is parallel
-                // and the shapes can slide against each other.  But we still
-                // need to check other axes to know if they'll actually touch.
-                slide_axis = Some(fullaxis);
-                continue;
-            }
+fn convex_touching<A: Convex + ?Sized, B: Convex + ?Sized>(ours: &A, theirs: &B, tolerance: WorldUnit) -> Option<(WorldUnit, Vector)> {
+    if ! ours.bbox().inflate(tolerance, tolerance).touches(&theirs.bbox()) {
+        return None;
+    }
+    match convex_separation(ours, theirs) {
+        Some((gap, normal)) if gap >= 0 && gap <= tolerance => Some((gap, normal)),
+        _ => None,
+    }
+}
+
+/// How far we'd have to move to stop overlapping, if we overlap at all.
+fn convex_penetration<A: Convex + ?Sized, B: Convex + ?Sized>(ours: &A, theirs: &B) -> Option<Vector> {
+    if ! ours.bbox().intersects(&theirs.bbox()) {
+        return None;
+    }
+    match convex_separation(ours, theirs) {
+        Some((gap, normal)) if gap < 0 => Some(normal * -gap),
+        _ => None,
+    }
+}
+
+fn box_overlaps(ours: &Rect, theirs: &Rect) -> bool {
+    interval_gap(ours.min_x(), ours.max_x(), theirs.min_x(), theirs.max_x()) < 0 &&
+        interval_gap(ours.min_y(), ours.max_y(), theirs.min_y(), theirs.max_y()) < 0
+}
+
+/// Distance between two intervals, negative if they overlap, fudged the same way as
+/// axial_projections.
+fn interval_gap(min1: Fixed, max1: Fixed, min2: Fixed, max2: Fixed) -> Fixed {
+    if min1 < min2 {
+        fudge_to_zero(min2 - max1)
+    }
+    else {
+        fudge_to_zero(min1 - max2)
+    }
+}
+
+/// Raycast against any convex shape.  Only knows how to do rounded shapes with one or two points,
+/// i.e. circles and capsules, but those are the only kinds there are.
+fn convex_raycast<A: Convex + ?Sized>(shape: &A, ray: &Ray) -> Option<(WorldUnit, Vector)> {
+    if ! ray.bbox().touches(&shape.bbox()) {
+        return None;
+    }
+
+    let points = shape.vertices();
+    let radius = shape.radius();
+    if radius == 0 {
+        return polygon_raycast(points, ray);
+    }
+
+    let mut best = None;
+    for &point in points {
+        keep_nearest(&mut best, circle_raycast(point, radius, ray));
+    }
+    if points.len() == 2 && points[0] != points[1] {
+        // The straight part of a capsule is a rectangle along the spine
+        let offset = (points[1] - points[0]).perpendicular().normalize() * radius;
+        let body = [points[0] + offset, points[1] + offset, points[1] - offset, points[0] - offset];
+        keep_nearest(&mut best, polygon_raycast(&body, ray));
+    }
+    best
+}
+
+fn keep_nearest(best: &mut Option<(WorldUnit, Vector)>, hit: Option<(WorldUnit, Vector)>) {
+    if let Some(hit) = hit {
+        if best.map_or(true, |best| hit.0 < best.0) {
+            *best = Some(hit);
+        }
+    }
+}
 
-            // Figure out how much movement is allowed, as a fraction.
-            // Conceptually, the answer is the movement projected onto the
-            // axis, divided by the separation projected onto the same
-            // axis.  Stuff cancels, and it turns out to be just the ratio
-            // of dot products (which makes sense).  Vectors are neat.
-            // Note that slides are meaningless here; a shape could move
-            // perpendicular to the axis forever without hitting anything.
-            let numer = sep.dot(fullaxis);
-            let amount = fudge_to_zero(numer / dot);
-            /* TODO right?
-            if amount > PRECISION + Fixed::promote(1) {
+fn keep_deepest(best: &mut Option<Vector>, push: Option<Vector>) {
+    if let Some(push) = push {
+        if best.map_or(true, |best| push.square_length() > best.square_length()) {
+            *best = Some(push);
+        }
+    }
+}
+
+/// Raycast against a convex polygon, by clipping the ray against each edge in turn.
+fn polygon_raycast(points: &[Point], ray: &Ray) -> Option<(WorldUnit, Vector)> {
+    // Averaging the corners gives a point that's definitely inside, which tells us which way each
+    // edge faces, whichever way the points wind
+    let mut sum = Vector::zero();
+    for &point in points {
+        sum += point.to_vector();
+    }
+    let inside = (sum / WorldUnit::promote(points.len() as i16)).to_point();
+
+    let mut enter = WorldUnit::from(0);
+    let mut exit = ray.length;
+    let mut enter_normal = None;
+    let mut prev_point = points[points.len() - 1];
+    for &point in points {
+        let mut normal = (point - prev_point).perpendicular();
+        let edge_start = prev_point;
+        prev_point = point;
+        if normal == Vector::zero() {
+            continue;
+        }
+        if normal.dot(inside - edge_start) > 0 {
+            normal = -normal;
+        }
+
+        // Points on the inside of this edge satisfy distance * denom <= numer
+        let numer = normal.dot(edge_start - ray.start);
+        let denom = normal.dot(ray.direction);
+        if denom == 0 {
+            // Parallel, so we're either always inside this edge or never
+            if numer < 0 {
                 return None;
             }
-            */
-
-            // TODO i think i could avoid this entirely by using a cross
-            // product instead?
-            // FIXME i had to fix this here, so fix it in LÖVE too.  but also in fact, uh,
-            // maybe write some tests and rejigger this code a bit too
-            if maxamt > Fixed::min_value() && (amount - maxamt).abs() < PRECISION {
-                // Equal, ish
-            }
-            else if amount > maxamt {
-                maxamt = amount;
-                maxnumer = numer;
-                maxdenom = dot;
-                // XXX normals normals = {};
-                left_norm = None;
-                right_norm = None;
-                left_max_dot = Fixed::min_value();
-                right_max_dot = Fixed::min_value();
+        }
+        else if denom < 0 {
+            // Heading inwards through this edge
+            let distance = numer / denom;
+            if distance >= enter {
+                enter = distance;
+                enter_normal = Some(normal);
             }
-            else {
-                continue;
+        }
+        else {
+            // Heading outwards through this edge
+            let distance = numer / denom;
+            if distance < exit {
+                exit = distance;
             }
+        }
 
-            // XXX used to continue if this is a move normal
-            // Now all that's left to do is merge the collision normal
-            // far
-
-            // FIXME these are no longer de-duplicated, hmm
-            let mut normal = -fullaxis;
-            // XXX normals normals[normal] = -axis;
-
-            let mut ourdot = -(movement.dot(axis));
-            // Flip normals that face away from us
-            // TODO justification is that i only use two axes for box/box collisions, and if i
-            // want to handle normals facing away, i should really be doing it earlier in this
-            // loop -- though i'm not sure how this would work for a line collider?
-            if ourdot > 0 {
-                //continue;
-                ourdot = -ourdot;
-                normal = -normal;
-            }
+        if enter > exit {
+            return None;
+        }
+    }
+
+    match enter_normal {
+        Some(normal) => Some((enter, normal.normalize())),
+        // Never crossed an edge on the way in, so we started inside
+        None => Some((0.into(), -ray.direction)),
+    }
+}
 
-            // Determine if this normal is on our left or right
-            let perpdot = movenormal.dot(normal);
+fn circle_raycast(center: Point, radius: WorldUnit, ray: &Ray) -> Option<(WorldUnit, Vector)> {
+    // Same trick as corner_axis: solve |offset + s * direction| = radius
+    let offset = ray.start - center;
+    let along = offset.dot(ray.direction);
+    let across = offset.dot(ray.direction.perpendicular());
+    let discriminant = radius * radius - across * across;
+    if discriminant < 0 {
+        return None;
+    }
+    let root = discriminant.sqrt();
+    if -along + root < 0 {
+        // Entirely behind us
+        return None;
+    }
 
-            // TODO explain this better, but the idea is: using the greater dot means using the slope that's furthest away from us, which resolves corners nicely because two normals on one side HAVE to be a corner, they can't actually be one in front of the other
-            // TODO should these do something on a tie?
-            if perpdot <= PRECISION && ourdot > left_max_dot {
-                left_norm = Some(normal);
-                left_max_dot = ourdot;
-            }
-            if perpdot >= -PRECISION && ourdot > right_max_dot {
-                right_norm = Some(normal);
-                right_max_dot = ourdot;
-            }
+    let distance = -along - root;
+    if distance <= 0 {
+        return Some((0.into(), -ray.direction));
+    }
+    else if distance > ray.length {
+        return None;
+    }
+    let point = ray.start + ray.direction * distance;
+    Some((distance, (point - center) / radius))
+}
+
+fn flip_point_x(point: Point, axis: WorldUnit) -> Point {
+    Point::new(axis * 2 - point.x, point.y)
+}
+
+/// Mirror a rect across the vertical line x = axis.
+pub fn flip_rect_x(rect: &Rect, axis: WorldUnit) -> Rect {
+    Rect::new(Point::new(axis * 2 - rect.max_x(), rect.origin.y), rect.size)
+}
+
+/// Extend a rect along a movement vector (to enclose all space it might cross along the way)
+fn extend_rect(rect: &Rect, d: Vector) -> Rect {
+    let mut rect = rect.clone();
+
+    if d.x < 0 {
+        rect.origin.x += d.x;
+        rect.size.width -= d.x;
+    }
+    else if d.x > 0 {
+        rect.size.width += d.x;
+    }
+
+    if d.y < 0 {
+        rect.origin.y += d.y;
+        rect.size.height -= d.y;
+    }
+    else if d.y > 0 {
+        rect.size.height += d.y;
+    }
+
+    rect
+}
+
+/// `slide_towards` for two axis-aligned boxes, which is what nearly every collision is.
+///
+/// This feeds the exact same numbers to the same `Sweep` as the general version, but the only
+/// axes are ±x and ±y, which are already unit length, so there's no square root and every dot
+/// product is just picking out (and maybe negating) one coordinate.
+fn box_slide_towards(ours: &Rect, theirs: &Rect, movement: Vector) -> Option<Collision> {
+    if ! extend_rect(ours, movement).intersects(theirs) {
+        return None;
+    }
+
+    let mut sweep = Sweep::new(movement);
+
+    let axes = [
+        (vec2(1, 0), ours.min_x(), ours.max_x(), theirs.min_x(), theirs.max_x(), movement.x),
+        (vec2(0, 1), ours.min_y(), ours.max_y(), theirs.min_y(), theirs.max_y(), movement.y),
+    ];
+    for &(fullaxis, min1, max1, min2, max2, along) in &axes {
+        // Same as axial_projections, except that sep dotted with the axis is always just the gap
+        let blocked = if min1 < min2 {
+            let gap = min2 - max1;
+            sweep.add_axis(fullaxis, fudge_to_zero(gap), gap, along, along)
+        }
+        else {
+            let gap = min1 - max2;
+            sweep.add_axis(-fullaxis, fudge_to_zero(gap), gap, -along, -along)
+        };
+        if blocked {
+            return None;
         }
+    }
+    sweep.finish()
+}
+
+/// Running state for `slide_towards`, which looks at one axis at a time and then assembles a
+/// `Collision` from the most restrictive one(s).  Split out so the box fast path can share it.
+struct Sweep {
+    movement: Vector,
+    // FIXME is the move normal actually necessary, or was it just covering up
+    // my bad math before?
+    movenormal: Vector,
+
+    left_max_dot: Fixed,
+    left_norm: Option<Vector>,
+    right_max_dot: Fixed,
+    right_norm: Option<Vector>,
+
+    maxamt: Fixed,
+    maxnumer: Fixed,
+    maxdenom: Fixed,
+    touchtype: Contact,
+    slide_axis: Option<Vector>,
+}
+
+impl Sweep {
+    fn new(movement: Vector) -> Sweep {
+        Sweep{
+            movement,
+            movenormal: movement.perpendicular(),
+
+            left_max_dot: Fixed::min_value(),
+            left_norm: None,
+            right_max_dot: Fixed::min_value(),
+            right_norm: None,
+
+            // Project both shapes onto each axis and look for the minimum distance
+            maxamt: Fixed::min_value(),
+            maxnumer: 1.into(),
+            maxdenom: 1.into(),
+            // The semantics of the SAT are that the shapes overlap unless at least one axis
+            // shows they don't, hence the default of Overlap here
+            touchtype: Contact::Overlap,
+            slide_axis: None,
+        }
+    }
+
+    /// Account for one projection axis.  `fullaxis` points from us towards them, `dist` is the
+    /// (fudged) gap between the shapes along it, `numer` is the separation dotted with
+    /// `fullaxis`, and `fulldot` and `axisdot` are the movement dotted with `fullaxis` and with
+    /// its normalized version.
+    ///
+    /// Returns true if this axis proves the shapes can't collide at all.
+    fn add_axis(&mut self, fullaxis: Vector, dist: Fixed, numer: Fixed, fulldot: Fixed, axisdot: Fixed) -> bool {
+        // Negative distance means the shapes overlap from this perspective, which is
+        // inconclusive
+        if dist < 0 {
+            return false;
+        }
+
+        // Update touchtype
+        if dist > 0 {
+            self.touchtype = Contact::Collide;
+        }
+        // XXX excuse me what
+        else if self.touchtype == Contact::Overlap {
+            self.touchtype = Contact::Touch;
+        }
+
+        // This dot product is positive if we're moving closer along this
+        // axis, negative if we're moving away
+        let dot = fudge_to_zero(fulldot);
+
+        if dot < 0 || (dot == 0 && dist > 0) {
+            // Even if the shapes are already touching, they're not moving
+            // closer together, so they can't possibly collide.  Stop here.
+            // FIXME this means collision detection is not useful for finding touches
+            return true;
+        }
+        else if dist == 0 && dot == 0 {
+            // Zero dot and zero distance mean the movement is parallel
+            // and the shapes can slide against each other.  But we still
+            // need to check other axes to know if they'll actually touch.
+            self.slide_axis = Some(fullaxis);
+            return false;
+        }
+
+        // Figure out how much movement is allowed, as a fraction.
+        // Conceptually, the answer is the movement projected onto the
+        // axis, divided by the separation projected onto the same
+        // axis.  Stuff cancels, and it turns out to be just the ratio
+        // of dot products (which makes sense).  Vectors are neat.
+        // Note that slides are meaningless here; a shape could move
+        // perpendicular to the axis forever without hitting anything.
+        let amount = fudge_to_zero(numer / dot);
+        /* TODO right?
+        if amount > PRECISION + Fixed::promote(1) {
+            return true;
+        }
+        */
+
+        // TODO i think i could avoid this entirely by using a cross
+        // product instead?
+        // FIXME i had to fix this here, so fix it in LÖVE too.  but also in fact, uh,
+        // maybe write some tests and rejigger this code a bit too
+        if self.maxamt > Fixed::min_value() && (amount - self.maxamt).abs() < PRECISION {
+            // Equal, ish
+        }
+        else if amount > self.maxamt {
+            self.maxamt = amount;
+            self.maxnumer = numer;
+            self.maxdenom = dot;
+            // XXX normals normals = {};
+            self.left_norm = None;
+            self.right_norm = None;
+            self.left_max_dot = Fixed::min_value();
+            self.right_max_dot = Fixed::min_value();
+        }
+        else {
+            return false;
+        }
+
+        // XXX used to continue if this is a move normal
+        // Now all that's left to do is merge the collision normal
+        // far
+
+        // FIXME these are no longer de-duplicated, hmm
+        let mut normal = -fullaxis;
+        // XXX normals normals[normal] = -axis;
+
+        let mut ourdot = -axisdot;
+        // Flip normals that face away from us
+        // TODO justification is that i only use two axes for box/box collisions, and if i
+        // want to handle normals facing away, i should really be doing it earlier in this
+        // loop -- though i'm not sure how this would work for a line collider?
+        if ourdot > 0 {
+            ourdot = -ourdot;
+            normal = -normal;
+        }
+
+        // Determine if this normal is on our left or right
+        let perpdot = self.movenormal.dot(normal);
+
+        // TODO explain this better, but the idea is: using the greater dot means using the slope that's furthest away from us, which resolves corners nicely because two normals on one side HAVE to be a corner, they can't actually be one in front of the other
+        // TODO should these do something on a tie?
+        if perpdot <= PRECISION && ourdot > self.left_max_dot {
+            self.left_norm = Some(normal);
+            self.left_max_dot = ourdot;
+        }
+        if perpdot >= -PRECISION && ourdot > self.right_max_dot {
+            self.right_norm = Some(normal);
+            self.right_max_dot = ourdot;
+        }
+
+        false
+    }
+
+    /// Turn what we've learned from all the axes into a result.
+    fn finish(self) -> Option<Collision> {
+        let Sweep{ movement, movenormal, mut left_max_dot, mut left_norm, mut right_max_dot, mut right_norm, maxamt, maxnumer, maxdenom, touchtype, slide_axis } = self;
 
         if touchtype == Contact::Overlap {
             // Shapes are already colliding
-            // FIXME should have /some/ kind of gentle rejection here; should be
-            // easier now that i have touchdist
+            // Nothing to do about it here; whoever's moving should push themselves out with
+            // `penetration`, over a few frames if need be
             //error("seem to be inside something!!  stopping so you can debug buddy  <3")
             return Some(Collision{
                 movement: Vector::zero(),
@@ -707,45 +1492,6 @@ impl Polygon {
     }
 }
 
-// XXX no multi shape support
-/*
-function Polygon:_multi_slide_towards(other, movement)
-    local ret
-    for _, subshape in ipairs(other.subshapes) do
-        local collision = self:slide_towards(subshape, movement)
-        if collision == nil then
-            -- Do nothing
-        elseif ret == nil then
-            -- First result; just accept it
-            ret = collision
-        else
-            -- Need to combine
-            if collision.amount < ret.amount then
-                ret = collision
-            elseif collision.amount == ret.amount then
-                ret.touchdist = math.min(ret.touchdist, collision.touchdist)
-                if ret.touchtype == 0 then
-                    ret.touchtype = collision.touchtype
-                end
-                -- FIXME would be nice to de-dupe here too
-                for full, norm in pairs(collision.normals) do
-                    ret.normals[full] = norm
-                end
-                if collision.left_normal_dot > ret.left_normal_dot then
-                    ret.left_normal_dot = collision.left_normal_dot
-                    ret.left_normal = collision.left_normal
-                end
-                if collision.right_normal_dot > ret.right_normal_dot then
-                    ret.right_normal_dot = collision.right_normal_dot
-                    ret.right_normal = collision.right_normal
-                end
-            end
-        end
-    end
-
-    return ret
-end
-*/
 
 
 /*
@@ -773,10 +1519,6 @@ function Box:__tostring()
     return "<Box>"
 end
 
-function Box:flipx(axis)
-    return Box(axis * 2 - self.x0 - self.width, self.y0, self.width, self.height)
-end
-
 function Box:_generate_normals()
 end
 
@@ -784,3 +1526,217 @@ function Box:center()
     return self.x0 + self.width / 2, self.y0 + self.height / 2
 end
 */
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::geom::{point2, rect};
+
+    /// Both ways of sweeping two boxes, which had better agree exactly.
+    fn both_box_sweeps(ours: Rect, theirs: Rect, movement: Vector) -> (Option<Collision>, Option<Collision>) {
+        let general = sat_slide_towards(&Polygon::from_rect(ours), &Polygon::from_rect(theirs), movement);
+        (box_slide_towards(&ours, &theirs, movement), general)
+    }
+
+    #[test]
+    fn box_sweep_matches_general() {
+        let half = fixed!(0.5);
+        let ours = rect(0, 0, 10, 10);
+        let cases = [
+            // Head on, from each side
+            (rect(20, 0, 10, 10), vec2(15, 0)),
+            (rect(-20, 0, 10, 10), vec2(-15, 0)),
+            (rect(0, 20, 10, 10), vec2(0, 15)),
+            (rect(0, -20, 10, 10), vec2(0, -15)),
+            // Diagonally, into a face and right into a corner
+            (rect(20, 5, 10, 10), vec2(15, 4)),
+            (rect(20, 20, 10, 10), vec2(15, 15)),
+            (rect(-20, 20, 10, 10), vec2(-15, 15)),
+            // Corners that only just meet, or only just miss
+            (rect(20, 10, 10, 10), vec2(10, 0)),
+            (rect(20, 11, 10, 10), vec2(15, 0)),
+            // Already touching: pushing in, sliding along, backing away, and corner to corner
+            (rect(10, 0, 10, 10), vec2(5, 0)),
+            (rect(10, 0, 10, 10), vec2(0, 5)),
+            (rect(10, 0, 10, 10), vec2(5, 5)),
+            (rect(10, 0, 10, 10), vec2(-5, 0)),
+            (rect(10, 10, 10, 10), vec2(5, 5)),
+            (rect(10, 10, 10, 10), vec2(5, -5)),
+            (rect(-100, 10, 300, 10), vec2(3, 2)),
+            // Overlapping, moving or not
+            (rect(5, 5, 10, 10), vec2(3, 0)),
+            (rect(5, 5, 10, 10), vec2(0, 0)),
+            (rect(-5, -5, 30, 30), vec2(-2, 7)),
+            // Not moving at all, touching or apart
+            (rect(10, 0, 10, 10), vec2(0, 0)),
+            (rect(10, 10, 10, 10), vec2(0, 0)),
+            (rect(30, 0, 10, 10), vec2(0, 0)),
+            // Missing entirely
+            (rect(20, 20, 10, 10), vec2(15, 0)),
+            (rect(20, 0, 10, 10), vec2(-15, 0)),
+        ];
+        for &(theirs, movement) in cases.iter() {
+            let (fast, general) = both_box_sweeps(ours, theirs, movement);
+            assert_eq!(fast, general, "{:?} moving {:?}", theirs, movement);
+            // And the same again off the pixel grid
+            let theirs = theirs.translate(&Vector::new(half, -half));
+            let (fast, general) = both_box_sweeps(ours, theirs, movement + Vector::new(half, half));
+            assert_eq!(fast, general, "{:?} moving {:?}", theirs, movement);
+        }
+
+        // Some outcomes worth pinning down outright
+        let (hit, _) = both_box_sweeps(ours, rect(20, 0, 10, 10), vec2(15, 0));
+        let hit = hit.unwrap();
+        assert_eq!((hit.movement, hit.touchtype), (vec2(10, 0), Contact::Collide));
+        assert_eq!((hit.left_normal, hit.right_normal), (Some(vec2(-1, 0)), Some(vec2(-1, 0))));
+        let (hit, _) = both_box_sweeps(ours, rect(5, 5, 10, 10), vec2(3, 0));
+        assert_eq!(hit.unwrap().touchtype, Contact::Overlap);
+        let (hit, _) = both_box_sweeps(ours, rect(30, 0, 10, 10), vec2(0, 0));
+        assert!(hit.is_none());
+    }
+
+    #[test]
+    fn box_sweep_matches_general_everywhere() {
+        // Plain LCG, so this is the same every run; mostly whole pixels, so touches come up often
+        let mut state: u32 = 12345;
+        let mut next = |n: i32| {
+            state = state.wrapping_mul(1_103_515_245).wrapping_add(12345);
+            ((state >> 8) % n as u32) as i32
+        };
+        let coord = |n: i32, next: &mut dyn FnMut(i32) -> i32| {
+            if next(3) == 0 {
+                Fixed::from_bits(next(n * 256) - n * 128)
+            }
+            else {
+                Fixed::from(((next(n) - n / 2)) as i16)
+            }
+        };
+        for _ in 0 .. 20000 {
+            let ours = Rect::new(Point::new(coord(32, &mut next), coord(32, &mut next)), Size::new(Fixed::from(next(16) as i16 + 1), Fixed::from(next(16) as i16 + 1)));
+            let theirs = Rect::new(Point::new(coord(32, &mut next), coord(32, &mut next)), Size::new(Fixed::from(next(32) as i16 + 1), Fixed::from(next(16) as i16 + 1)));
+            let movement = Vector::new(coord(16, &mut next), coord(16, &mut next));
+            let (fast, general) = both_box_sweeps(ours, theirs, movement);
+            assert_eq!(fast, general, "{:?} vs {:?} moving {:?}", ours, theirs, movement);
+        }
+    }
+
+    /// Close enough, for things that go through a square root somewhere along the way.
+    fn assert_near(actual: Vector, expected: (f32, f32)) {
+        let (x, y) = (actual.x.to_bits() as f32 / 256., actual.y.to_bits() as f32 / 256.);
+        assert!((x - expected.0).abs() < 0.1 && (y - expected.1).abs() < 0.1, "{:?} isn't near {:?}", actual, expected);
+    }
+
+    #[test]
+    fn circle_sweeps() {
+        let ball = Circle::new(point2(0, 0), fixed!(4));
+
+        // Straight into a wall
+        let hit = convex_slide_towards(&ball, &Polygon::from_rect(rect(20, -20, 10, 40)), vec2(20, 0)).unwrap();
+        assert_eq!((hit.movement, hit.touchtype), (vec2(16, 0), Contact::Collide));
+        assert_eq!(hit.left_normal, Some(vec2(-1, 0)));
+
+        // Onto a corner, which the ball rounds off: it stops a radius short of the corner, on the
+        // diagonal, rather than where its bbox would hit
+        let hit = convex_slide_towards(&ball, &Polygon::from_rect(rect(10, 10, 10, 10)), vec2(10, 10)).unwrap();
+        assert_near(hit.movement, (7.17, 7.17));
+        assert_eq!(hit.touchtype, Contact::Collide);
+        // ...and past a corner its bbox would've clipped
+        assert!(convex_slide_towards(&ball, &Polygon::from_rect(rect(10, -14, 10, 10)), vec2(20, 0)).is_none());
+
+        // Another circle
+        let hit = convex_slide_towards(&ball, &Circle::new(point2(20, 0), fixed!(4)), vec2(20, 0)).unwrap();
+        assert_near(hit.movement, (12., 0.));
+
+        // Rolling down onto a slope, which stops it a radius away from the slope
+        let slope = Polygon::new(&[point2(0, 20), point2(20, 0), point2(20, 20)]);
+        let hit = convex_slide_towards(&ball, &slope, vec2(10, 10)).unwrap();
+        assert_near(hit.movement, (7.17, 7.17));
+        // Sitting on it, sliding along it, is a touch
+        let resting = Circle::new(point2(7, 7), fixed!(4));
+        let hit = convex_slide_towards(&resting, &slope, vec2(5, -5));
+        assert!(hit.map_or(true, |hit| hit.touchtype != Contact::Overlap));
+
+        // Already overlapping, or not moving at all, which can't hit anything even when touching
+        let hit = convex_slide_towards(&ball, &Polygon::from_rect(rect(2, -2, 10, 4)), vec2(5, 0)).unwrap();
+        assert_eq!(hit.touchtype, Contact::Overlap);
+        assert!(convex_slide_towards(&ball, &Polygon::from_rect(rect(4, -2, 10, 4)), vec2(0, 0)).is_none());
+        assert!(convex_slide_towards(&ball, &Polygon::from_rect(rect(8, -2, 10, 4)), vec2(0, 0)).is_none());
+    }
+
+    #[test]
+    fn capsule_sweeps() {
+        // Standing upright, walking into a wall
+        let upright = Capsule::new(point2(0, -8), point2(0, 8), fixed!(4));
+        let hit = convex_slide_towards(&upright, &Polygon::from_rect(rect(20, -20, 10, 40)), vec2(20, 0)).unwrap();
+        assert_eq!((hit.movement, hit.touchtype), (vec2(16, 0), Contact::Collide));
+
+        // Lying down, falling onto a floor
+        let lying = Capsule::new(point2(-8, 0), point2(8, 0), fixed!(4));
+        let hit = convex_slide_towards(&lying, &Polygon::from_rect(rect(-50, 10, 100, 10)), vec2(0, 20)).unwrap();
+        assert_eq!((hit.movement, hit.touchtype), (vec2(0, 6), Contact::Collide));
+        assert_eq!(hit.left_normal, Some(vec2(0, -1)));
+        // ...and off the edge of one, where only the rounded end catches the corner
+        let hit = convex_slide_towards(&lying, &Polygon::from_rect(rect(10, 10, 20, 10)), vec2(0, 20)).unwrap();
+        assert_near(hit.movement, (0., 6. + 4. - (16f32 - 4.).sqrt()));
+
+        // Tilted, against a circle, which is all rounded corners
+        let tilted = Capsule::new(point2(-8, -8), point2(8, 8), fixed!(2));
+        let hit = convex_slide_towards(&tilted, &Circle::new(point2(30, 8), fixed!(4)), vec2(20, 0)).unwrap();
+        assert_near(hit.movement, (16., 0.));
+
+        // A spine of zero length is just a circle
+        let dot = Capsule::new(point2(0, 0), point2(0, 0), fixed!(4));
+        let hit = convex_slide_towards(&dot, &Polygon::from_rect(rect(20, -20, 10, 40)), vec2(20, 0)).unwrap();
+        assert_eq!(hit.movement, vec2(16, 0));
+        // And one so short its normal is too small to measure shouldn't divide by zero
+        let tiny = Capsule::new(point2(0, 0), Point::new(Fixed::from_bits(1), Fixed::from_bits(1)), fixed!(4));
+        let hit = convex_slide_towards(&tiny, &Polygon::from_rect(rect(20, -20, 10, 40)), vec2(20, 0)).unwrap();
+        assert_eq!(hit.movement, vec2(16, 0) - Vector::new(Fixed::from_bits(1), Fixed::from_bits(0)));
+    }
+
+    #[test]
+    fn projecting_onto_nothing() {
+        let ball = Circle::new(point2(3, 4), fixed!(4));
+        assert_eq!(ball.project_onto_axis(Vector::zero()), (fixed!(0), fixed!(0), point2(3, 4), point2(3, 4)));
+        let (min, max, _, _) = ball.project_onto_axis(vec2(1, 0));
+        assert_eq!((min, max), (fixed!(-1), fixed!(7)));
+    }
+
+    #[test]
+    fn multishape_corner() {
+        // An L, moving diagonally into the inside corner so both pieces are hit at once
+        let corner = Shape::Multi(MultiShape::new(vec![
+            Polygon::from_rect(rect(0, 40, 64, 16)),
+            Polygon::from_rect(rect(40, 0, 16, 56)),
+        ]));
+        let lexy = Shape::Polygon(Polygon::from_rect(rect(25, 10, 12, 27)));
+        let hit = lexy.slide_towards(&corner, vec2(10, 10)).unwrap();
+        assert_eq!((hit.movement, hit.touchtype), (vec2(3, 3), Contact::Collide));
+        // One normal from each piece, one on each side
+        let mut normals = [hit.left_normal.unwrap(), hit.right_normal.unwrap()];
+        normals.sort_by_key(|normal| (normal.x, normal.y));
+        assert_eq!(normals, [vec2(-1, 0), vec2(0, -1)]);
+    }
+
+    #[test]
+    fn multishape_counts_a_surface_once() {
+        // Two pieces of the same slope, overlapping, with edges of different lengths
+        let first = || Polygon::new(&[point2(2, 27), point2(14, 21), point2(14, 67)]);
+        let second = || Polygon::new(&[point2(0, 28), point2(16, 20), point2(16, 68)]);
+        let lexy = Shape::Polygon(Polygon::from_rect(rect(-4, -10, 12, 27)));
+        let alone = lexy.slide_towards(&Shape::Polygon(first()), vec2(0, 20)).unwrap();
+        let other = lexy.slide_towards(&Shape::Polygon(second()), vec2(0, 20)).unwrap();
+        // Same surface, same place, but not quite the same normal, and rounding makes the second
+        // one look a tiny bit better
+        assert_eq!((alone.movement, other.movement), (vec2(0, 7), vec2(0, 7)));
+        assert!(alone.left_normal != other.left_normal);
+        assert!(same_direction(alone.left_normal, other.left_normal));
+        assert!(other.left_normal_dot > alone.left_normal_dot);
+
+        // Together, it's as though the second piece weren't there at all
+        let slope = Shape::Multi(MultiShape::new(vec![first(), second()]));
+        let hit = lexy.slide_towards(&slope, vec2(0, 20)).unwrap();
+        assert_eq!(hit, alone);
+    }
+}
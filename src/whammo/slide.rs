@@ -0,0 +1,527 @@
+/// Turning a pile of collisions into somewhere to actually go.
+
+use crate::geom::{Vector, VectorExt};
+use super::{CollisionResult, CollisionVec, Crush, Squeeze};
+use super::shapes::{Collision, Contact, Shape, fudge_to_zero};
+
+pub enum SlideResult {
+    Stuck,
+    Slid(Vector),
+}
+
+/// Put collisions in the order we'll touch them.  On a tie, overlaps come first, then touches,
+/// then collisions, same as klinklang.
+pub fn sort_collisions(collisions: &mut CollisionVec) {
+    collisions.as_mut_slice().sort_unstable_by_key(|collision| (collision.touchdist, collision.touchtype));
+}
+
+/// Does this collision have any say in where we can go?  Not if we're moving away from every one
+/// of its surfaces, which happens when the direction isn't the one we swept with, e.g. because
+/// something changed our velocity.  Surfaces we're sliding along exactly still count, since
+/// sliding away from one of those might mean sliding into another.
+fn constrains(collision: &Collision, direction: Vector) -> bool {
+    collision.left_normal.iter().chain(collision.right_normal.iter())
+        .any(|&normal| fudge_to_zero(direction.dot(normal)) <= 0)
+}
+
+/// Find the direction closest to `direction` that doesn't push into anything we hit.
+pub fn slide_along_normals(hits: &CollisionVec, direction: Vector) -> SlideResult {
+    let mut minleftdot = None;
+    let mut minleftnorm = None;
+    let mut minrightdot = None;
+    let mut minrightnorm = None;
+    let mut right_possible = true;
+    let mut left_possible = true;
+
+    // So, here's the problem.  At first blush, this seems easy enough: just
+    // pick the normal that restricts us the most, which is the one that faces
+    // most towards us (i.e. has the most negative dot product), and slide
+    // along that.  Alas, there are two major problems there.
+    // 1. We might be blocked on /both sides/ and thus can't move at all.  To
+    // detect this, we have to sort normals into "left" and "right", find the
+    // worst normal on each side, and then reconcile at the end.
+    // 2. Each hit might be a corner collision and have multiple normals.
+    // While hitting more objects and thus encountering more normals will
+    // /reduce/ our available slide area, hitting a corner /increases/ it.  So
+    // within a single hit, we have to do the same thing in reverse, finding
+    // the BEST normal on each side and counting that one.  whammo already does
+    // that part, so each hit has at most one normal per side; for a MultiShape,
+    // `merge_collision` does the same across its pieces, counting a surface
+    // that shows up in several pieces only once.
+    for collision in hits.iter() {
+        if collision.touchtype == Contact::Overlap /* || collision.passable */ {
+            continue;
+        }
+        if ! constrains(collision, direction) {
+            continue;
+        }
+
+        // The dots were worked out against the movement we swept with, not against `direction`.
+        // That's fine, since only their order matters here, and `direction` is always parallel to
+        // what we swept with: it's either the leftover movement, or a velocity that started out
+        // the same and has since been slid along the same normals.  (If something else changed
+        // it, `constrains` has already thrown out anything we're now moving away from.)
+        // Ties go to the later collision, but that hardly matters: two normals on the same side
+        // with the same dot are the same normal, give or take rounding, like the tops of two
+        // tiles in a floor.
+
+        if left_possible && collision.left_normal.is_some() {
+            if minleftdot.map_or(true, |dot| collision.left_normal_dot <= dot) {
+                minleftdot = Some(collision.left_normal_dot);
+                minleftnorm = collision.left_normal;
+            }
+        }
+        else {
+            left_possible = false;
+            minleftnorm = None;
+        }
+
+        if right_possible && collision.right_normal.is_some() {
+            if minrightdot.map_or(true, |dot| collision.right_normal_dot <= dot) {
+                minrightdot = Some(collision.right_normal_dot);
+                minrightnorm = collision.right_normal;
+            }
+        }
+        else {
+            right_possible = false;
+            minrightnorm = None;
+        }
+    }
+
+    if minleftdot.is_none() && minrightdot.is_none() && left_possible && right_possible {
+        // Nothing in the way at all, so we're free to move wherever
+        return SlideResult::Slid(direction);
+    }
+    if ! left_possible && ! right_possible {
+        return SlideResult::Stuck;
+    }
+
+    let axis;
+    if ! left_possible {
+        axis = minrightnorm;
+    }
+    else if ! right_possible {
+        axis = minleftnorm;
+    }
+    else if minleftdot > minrightdot {
+        axis = minleftnorm;
+    }
+    else {
+        axis = minrightnorm;
+    }
+
+    if let Some(axis) = axis {
+        // This dot product check handles an obscure case: if a collision callback
+        // overwrites our velocity so that we're moving /away/ from the object we
+        // hit, then there's no need to change it any more.  This happens with the
+        // moo form's charge in fox flux.
+        if direction.dot(axis) < 0 {
+            SlideResult::Slid(direction - direction.project_on(axis))
+        }
+        else {
+            SlideResult::Slid(direction)
+        }
+    }
+    else {
+        SlideResult::Stuck
+    }
+}
+
+
+/// Sort out what a sweep found: put the collisions in the order we'll touch them, and cut the
+/// movement short at the first one that blocks us.  Anything tied with that one stays, since we
+/// hit it at the same moment, but anything further along gets dropped.
+fn trim_collisions(result: &mut CollisionResult) {
+    let attempted = result.allowed;
+    let collisions = &mut result.collisions;
+
+    sort_collisions(collisions);
+
+    // Look through the objects we'll hit, in the order we'll /touch/ them,
+    // and stop at the first that blocks us
+    let mut allowed_amount = None;
+    let mut allowed_movement = attempted;
+    let mut trim_collisions_to = collisions.len();
+    for (i, collision) in collisions.iter().enumerate() {
+        // FIXME collision.attempted = attempted
+
+        // If we've already found something that blocks us, and this
+        // collision requires moving further, then stop here.  This allows
+        // for ties
+        if let Some(allowed_amount) = allowed_amount {
+            if allowed_amount < collision.amount {
+                trim_collisions_to = i;
+                break;
+            }
+        }
+
+        // Check if the other shape actually blocks us
+        /* XXX no need for this yet; if there's a shape, it blocks
+        local passable = pass_callback and pass_callback(collision)
+        if passable == 'retry' then
+            -- Special case: the other object just moved, so keep moving
+            -- and re-evaluate when we hit it again.  Useful for pushing.
+            if i > 1 and collisions[i - 1].shape == collision.shape then
+                -- To avoid loops, don't retry a shape twice in a row
+                passable = false
+            else
+                local new_collision = shape:slide_towards(collision.shape, attempted)
+                if new_collision then
+                    new_collision.shape = collision.shape
+                    for j = i + 1, #collisions + 1 do
+                        if j > #collisions or not _collision_sort(collisions[j], new_collision) then
+                            table.insert(collisions, j, new_collision)
+                            break
+                        end
+                    end
+                end
+            end
+        end
+        */
+        let passable = false;
+
+        // If we're hitting the object and it's not passable, stop here
+        if allowed_amount.is_none() && ! passable && collision.touchtype == Contact::Collide {
+            allowed_amount = Some(collision.amount);
+            allowed_movement = collision.movement;
+        }
+
+        // Log the last contact with each shape
+        // XXX collision.passable = passable
+        // XXX hits[collision.shape] = collision
+    }
+
+    collisions.truncate(trim_collisions_to);
+    result.allowed = allowed_movement;
+}
+
+/// How a `nudge` went.
+pub struct Nudge {
+    /// How far it actually got
+    pub movement: Vector,
+    /// Set if it stopped short because it couldn't slide any further, rather than because it ran
+    /// out of movement
+    pub stuck: bool,
+    /// Set if it got stuck between two surfaces facing each other
+    pub crush: Option<Crush>,
+    /// Set if it started out inside something, which it'll need pushing out of
+    pub overlapped: bool,
+    /// Set if any sweep found more collisions than would fit.  Not fatal, but it means it might
+    /// have slid along the wrong thing
+    pub truncated: bool,
+}
+
+fn _is_vector_almost_zero(vec: Vector) -> bool {
+    vec.x.abs() * 64 < 1 && vec.y.abs() * 64 < 1
+}
+
+/// Move a shape through the world by some amount, respecting collision.  `sweep` finds whatever
+/// the shape would run into moving by some amount, in any order.  `velocity` gets slid along the
+/// same surfaces, if whatever's moving has one; pass a dummy otherwise.
+pub fn nudge<F>(shape: &mut Shape, mut displacement: Vector, velocity: &mut Vector, mut sweep: F) -> Nudge
+    where F: FnMut(&Shape, Vector) -> CollisionResult
+{
+    // Main movement loop!  Try to slide in the direction of movement; if that
+    // fails, then try to project our movement along a surface we hit and
+    // continue, until we hit something head-on or run out of movement.
+    // TODO rename a LOT of these variables and properties, maybe in LÖVE too
+    let mut nudge = Nudge{ movement: Vector::zero(), stuck: false, crush: None, overlapped: false, truncated: false };
+    let mut stuck_counter = 0;
+    // TODO when things can push other things, the pusher should count as a surface too
+    let mut squeeze = Squeeze::new();
+    loop {
+        // TODO return hits up here?
+        let mut result = sweep(shape, displacement /*, pass_callback */);
+        trim_collisions(&mut result);
+        let CollisionResult{ allowed: successful, collisions: hits, truncated } = result;
+        // Movement just ignores whatever we're inside, so whoever's moving should get out of
+        // it some other way
+        nudge.overlapped |= hits.iter().any(|collision| collision.touchtype == Contact::Overlap);
+        nudge.truncated |= truncated;
+        squeeze.add_collisions(&hits);
+        shape.move_by(successful);
+        nudge.movement += successful;
+
+        /* XXX
+        if xxx_no_slide then
+            break
+        end
+        */
+        let remaining = displacement - successful;
+        // FIXME these values are completely arbitrary and i cannot justify them
+        if remaining.x.abs() * 16 < 1 && remaining.y.abs() * 16 < 1 {
+            break;
+        }
+
+        // FIXME this shouldn't be in here...  or should it?  only for self movement obviously
+        // but this seems like the right place?
+        match slide_along_normals(&hits, *velocity) {
+            SlideResult::Stuck => {
+                *velocity = Vector::zero();
+            }
+            SlideResult::Slid(new_velocity) => {
+                *velocity = new_velocity;
+            }
+        }
+
+        // Find the allowed slide direction that's closest to the direction of movement.
+        // TODO maybe this should just be Option haha
+        match slide_along_normals(&hits, remaining) {
+            SlideResult::Stuck => {
+                nudge.stuck = true;
+                break;
+            }
+            SlideResult::Slid(direction) => {
+                displacement = direction;
+            }
+        }
+
+        // FIXME why am i doing this twice
+        if displacement.x.abs() * 16 < 1 && displacement.y.abs() * 16 < 1 {
+            break;
+        }
+
+        // Automatically break if we don't move for three iterations -- not
+        // moving once is okay because we might slide, but three indicates a
+        // bad loop somewhere
+        // XXX well, wait, aren't we only REALLY stuck if the remaining movement didn't get
+        // smaller (or at least, change in some way at all)?  but i don't want to move more
+        // than 3 times anyway so maybe it's ok
+        if _is_vector_almost_zero(successful) {
+            stuck_counter += 1;
+            if stuck_counter >= 3 {
+                // FIXME interesting!  i get this when jumping against the crate in a corner in
+                // tech-1; i think because clocks can't handle single angles correctly, so this
+                // is the same problem as walking down a hallway exactly your own height -- is
+                // this still the case?
+                nudge.stuck = true;
+                break;
+            }
+        }
+    }
+
+    // Being stuck isn't so bad on its own, but being stuck between two things means we're
+    // getting squished
+    if nudge.stuck {
+        nudge.crush = squeeze.crush();
+    }
+
+    nudge
+}
+
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::fixed::Fixed;
+    use crate::geom::{Point, WorldWhole, point2, vec2};
+    use crate::whammo::CollisionResult;
+    use crate::whammo::shapes::{Polygon, Shape};
+
+    /// Lexy's gravity, near enough.
+    const GRAVITY: Fixed = fixed!(0.21);
+
+    fn rect(x: WorldWhole, y: WorldWhole, w: WorldWhole, h: WorldWhole) -> Shape {
+        Shape::Polygon(Polygon::from_rect(crate::geom::rect(x, y, w, h)))
+    }
+
+    fn lexy_at(x: WorldWhole, y: WorldWhole) -> Shape {
+        rect(x, y, 12, 27)
+    }
+
+    /// Nudge a shape through some walls, the same way Lexy moves through a place.
+    fn nudge_through(shape: &mut Shape, walls: &[Shape], displacement: Vector, velocity: &mut Vector) -> Nudge {
+        nudge(shape, displacement, velocity, |shape, attempted| {
+            let mut result = CollisionResult::new(attempted);
+            for wall in walls {
+                if let Some(hit) = shape.slide_towards(wall, attempted) {
+                    result.add(hit);
+                }
+            }
+            result
+        })
+    }
+
+    fn top_left(shape: &Shape) -> Point {
+        shape.bbox().origin
+    }
+
+    #[test]
+    fn hallway_exactly_her_height() {
+        let walls = [rect(-100, -16, 300, 16), rect(-100, 27, 300, 16)];
+        for &speed in &[fixed!(1.5), fixed!(-1.5)] {
+            let mut lexy = lexy_at(0, 0);
+            let mut velocity = Vector::new(speed, GRAVITY);
+            for _ in 0 .. 10 {
+                let Nudge{ movement: moved, stuck, .. } = nudge_through(&mut lexy, &walls, velocity, &mut velocity);
+                assert!(! stuck);
+                assert_eq!(moved, Vector::new(speed, 0.into()));
+                velocity.y += GRAVITY;
+            }
+            assert_eq!(top_left(&lexy), Point::new(speed * 10, 0.into()));
+        }
+    }
+
+    #[test]
+    fn floor_made_of_tiles() {
+        let walls: Vec<Shape> = (0 .. 10).map(|i| rect(i * 16, 27, 16, 16)).collect();
+        let mut lexy = lexy_at(0, 0);
+        for _ in 0 .. 40 {
+            let mut velocity = Vector::new(fixed!(1.5), GRAVITY);
+            let Nudge{ movement: moved, stuck, .. } = nudge_through(&mut lexy, &walls, velocity, &mut velocity);
+            assert!(! stuck);
+            assert_eq!(moved, Vector::new(fixed!(1.5), 0.into()));
+        }
+    }
+
+    #[test]
+    fn jump_into_corner() {
+        // Already against the wall on the right, and jumping up and right into the ceiling
+        let walls = [rect(12, -100, 16, 200), rect(-100, -16, 200, 16)];
+        let mut lexy = lexy_at(0, 2);
+        let mut velocity = vec2(2, -4);
+        let Nudge{ movement: moved, stuck, .. } = nudge_through(&mut lexy, &walls, velocity, &mut velocity);
+        assert!(! stuck);
+        assert_eq!(moved, vec2(0, -2));
+        assert_eq!(velocity, Vector::zero());
+        assert_eq!(top_left(&lexy), Point::new(0.into(), 0.into()));
+    }
+
+    #[test]
+    fn jump_into_ceiling() {
+        let walls = [rect(-100, -16, 300, 16)];
+        let mut lexy = lexy_at(0, 2);
+        let mut velocity = vec2(2, -4);
+        let Nudge{ movement: moved, stuck, .. } = nudge_through(&mut lexy, &walls, velocity, &mut velocity);
+        assert!(! stuck);
+        assert_eq!(moved, vec2(2, -2));
+        assert_eq!(velocity, vec2(2, 0));
+    }
+
+    #[test]
+    fn diagonal_hallway_exactly_her_size() {
+        // Floor is x + y > 39, ceiling is x + y < 0, so her corners fit exactly
+        let walls = [
+            Shape::Polygon(Polygon::new(&[point2(139, -100), point2(139, 100), point2(-61, 100)])),
+            Shape::Polygon(Polygon::new(&[point2(-100, -100), point2(100, -100), point2(-100, 100)])),
+        ];
+        let mut lexy = lexy_at(0, 0);
+        for _ in 0 .. 10 {
+            let mut velocity = Vector::new(fixed!(1.5), GRAVITY * 4);
+            let Nudge{ movement: moved, stuck, .. } = nudge_through(&mut lexy, &walls, velocity, &mut velocity);
+            assert!(! stuck);
+            assert!(moved.x > 0 && moved.x == -moved.y);
+        }
+    }
+
+    #[test]
+    fn wedged_between_walls() {
+        // Falling into a V that's too narrow for her, so she hits both sides at once
+        let walls = [
+            Shape::Polygon(Polygon::new(&[point2(-24, 0), point2(24, 48), point2(-24, 48)])),
+            Shape::Polygon(Polygon::new(&[point2(36, 0), point2(36, 48), point2(-12, 48)])),
+        ];
+        let hits = {
+            let lexy = lexy_at(0, -8);
+            let mut result = CollisionResult::new(vec2(0, 8));
+            for wall in &walls {
+                if let Some(hit) = lexy.slide_towards(wall, vec2(0, 8)) {
+                    result.add(hit);
+                }
+            }
+            result.collisions
+        };
+        assert_eq!(hits.len(), 2);
+        assert!(match slide_along_normals(&hits, vec2(0, 8)) {
+            SlideResult::Stuck => true,
+            SlideResult::Slid(direction) => direction == Vector::zero(),
+        });
+        // ...but backing out is fine
+        assert!(match slide_along_normals(&hits, vec2(0, -8)) {
+            SlideResult::Slid(direction) => direction == vec2(0, -8),
+            SlideResult::Stuck => false,
+        });
+    }
+
+    #[test]
+    fn free_movement() {
+        let hits = CollisionResult::new(vec2(1, 1)).collisions;
+        assert!(match slide_along_normals(&hits, vec2(3, -2)) {
+            SlideResult::Slid(direction) => direction == vec2(3, -2),
+            SlideResult::Stuck => false,
+        });
+    }
+
+    #[test]
+    fn trimmed_to_first_blocker() {
+        // Walking into a wall, with a second wall behind it and a copy of the first
+        let walls = [rect(18, -100, 8, 200), rect(16, -100, 8, 200), rect(16, -100, 8, 200)];
+        let lexy = lexy_at(0, 0);
+        let mut result = CollisionResult::new(vec2(20, 0));
+        for wall in &walls {
+            if let Some(hit) = lexy.slide_towards(wall, vec2(20, 0)) {
+                result.add(hit);
+            }
+        }
+        assert_eq!(result.collisions.len(), 3);
+        trim_collisions(&mut result);
+        assert_eq!(result.allowed, vec2(4, 0));
+        // Both copies of the near wall are hit at once, so they both stay, but the far one goes
+        let movements: Vec<_> = result.collisions.iter().map(|hit| hit.movement).collect();
+        assert_eq!(movements, [vec2(4, 0), vec2(4, 0)]);
+
+        // So nudging stops at the wall, and isn't stuck, just done
+        let mut lexy = lexy_at(0, 0);
+        let mut velocity = vec2(20, 0);
+        let result = nudge_through(&mut lexy, &walls, velocity, &mut velocity);
+        assert_eq!(result.movement, vec2(4, 0));
+        assert_eq!(velocity, Vector::zero());
+        assert!(! result.stuck && result.crush.is_none() && ! result.overlapped && ! result.truncated);
+    }
+
+    #[test]
+    fn crushed_in_a_slot() {
+        // Falling into a V with walls so steep they nearly face each other
+        let walls = [
+            Shape::Polygon(Polygon::new(&[point2(-20, 0), point2(5, 100), point2(-20, 100)])),
+            Shape::Polygon(Polygon::new(&[point2(32, 0), point2(32, 100), point2(7, 100)])),
+        ];
+        let mut lexy = lexy_at(0, 45);
+        let mut velocity = vec2(0, 12);
+        let result = nudge_through(&mut lexy, &walls, velocity, &mut velocity);
+        assert_eq!(result.movement, vec2(0, 8));
+        assert!(result.stuck);
+        let crush = result.crush.expect("should be crushed");
+        assert!(crush.normals.0.x * crush.normals.1.x < 0);
+
+        // A wider V is just somewhere to stand
+        let walls = [
+            Shape::Polygon(Polygon::new(&[point2(-24, 0), point2(24, 48), point2(-24, 48)])),
+            Shape::Polygon(Polygon::new(&[point2(36, 0), point2(36, 48), point2(-12, 48)])),
+        ];
+        let mut lexy = lexy_at(0, -8);
+        let mut velocity = vec2(0, 12);
+        let result = nudge_through(&mut lexy, &walls, velocity, &mut velocity);
+        assert_eq!(result.movement, vec2(0, 5));
+        assert!(result.stuck);
+        assert!(result.crush.is_none());
+    }
+
+    #[test]
+    fn tiny_movements() {
+        // Less than 1/16px left over isn't worth sliding along the wall for
+        let walls = [rect(12, -100, 16, 200)];
+        let mut lexy = lexy_at(0, 0);
+        let tiny = Vector::new(Fixed::from_bits(8), Fixed::from_bits(-8));
+        let mut velocity = tiny;
+        let result = nudge_through(&mut lexy, &walls, tiny, &mut velocity);
+        assert_eq!(result.movement, Vector::zero());
+        assert!(! result.stuck);
+
+        // Starting inside a wall doesn't stop anything, but it does get noticed
+        let mut velocity = vec2(2, 0);
+        let result = nudge_through(&mut lexy_at(8, 0), &walls, velocity, &mut velocity);
+        assert_eq!(result.movement, vec2(2, 0));
+        assert!(result.overlapped);
+    }
+}
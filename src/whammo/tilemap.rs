@@ -0,0 +1,198 @@
+/// Collision geometry for a place's tiles, built once and reused every frame.
+///
+/// Runs of solid tiles are merged into as few rectangles as we can easily manage, so a floor is
+/// one collider instead of thirty, and Lexy doesn't snag on the seams between tiles either.
+
+use arrayvec::ArrayVec;
+
+use crate::data::places::{Place, MAX_PLACE_DIMENSION};
+use crate::geom::{Rect, TileCoord, TilePoint, TileRect, tile_to_world_rect, tiles_to_world_rect};
+use super::materials::Material;
+
+/// Most merged rectangles we'll keep.  Anything past this is still solid, it just gets checked one
+/// tile at a time.
+pub const MAX_TILE_COLLIDERS: usize = 128;
+
+const NO_COLLIDER: u8 = 0xff;
+const OVERFLOW_COLLIDER: u8 = 0xfe;
+
+pub struct TileColliders {
+    colliders: ArrayVec<[Rect; MAX_TILE_COLLIDERS]>,
+    /// Which collider covers each tile, so lookups don't have to scan the whole list
+    owners: [[u8; MAX_PLACE_DIMENSION]; MAX_PLACE_DIMENSION],
+    dirty: bool,
+}
+
+impl TileColliders {
+    /// Starts out dirty; call `refresh` before using it.
+    pub fn new() -> Self {
+        TileColliders{
+            colliders: ArrayVec::new(),
+            owners: [[NO_COLLIDER; MAX_PLACE_DIMENSION]; MAX_PLACE_DIMENSION],
+            dirty: true,
+        }
+    }
+
+    /// Throw away the cached geometry, e.g. because a tile changed or we switched places.  It'll
+    /// be rebuilt at the next `refresh`.
+    pub fn invalidate(&mut self) {
+        self.dirty = true;
+    }
+
+    /// Rebuild the colliders if they're out of date.  Cheap to call every frame.  Returns true
+    /// if anything was rebuilt, in which case the tiles might have changed out from under anyone
+    /// standing on them.
+    pub fn refresh(&mut self, place: &Place) -> bool {
+        if ! self.dirty {
+            return false;
+        }
+        self.rebuild(place);
+        self.dirty = false;
+        true
+    }
+
+    fn rebuild(&mut self, place: &Place) {
+        self.colliders.clear();
+        for row in self.owners.iter_mut() {
+            for owner in row.iter_mut() {
+                *owner = NO_COLLIDER;
+            }
+        }
+
+        let width = place.width;
+        let height = place.height;
+
+        // Greedy: grab the first unclaimed solid tile, extend it as far right as it goes, then
+        // extend that whole run down as far as it goes.  Not optimal, but tiles are mostly laid
+        // out in horizontal strips anyway.  Only tiles made of the same stuff get merged, so
+        // anyone asking what they're standing on gets a straight answer
+        for ty in 0 .. height {
+            let mut tx = 0;
+            while tx < width {
+                if ! self.is_unclaimed_solid(place, tx, ty) {
+                    tx += 1;
+                    continue;
+                }
+
+                let material = place.tile_at(TilePoint::new(tx as TileCoord, ty as TileCoord)).material;
+                let mut x1 = tx + 1;
+                while x1 < width && self.is_mergeable(place, x1, ty, material) {
+                    x1 += 1;
+                }
+                let mut y1 = ty + 1;
+                while y1 < height && (tx .. x1).all(|x| self.is_mergeable(place, x, y1, material)) {
+                    y1 += 1;
+                }
+
+                let owner;
+                if self.colliders.is_full() {
+                    owner = OVERFLOW_COLLIDER;
+                }
+                else {
+                    owner = self.colliders.len() as u8;
+                    self.colliders.push(tiles_to_world_rect(euclid::rect(
+                        tx as TileCoord, ty as TileCoord, (x1 - tx) as TileCoord, (y1 - ty) as TileCoord)));
+                }
+                for row in &mut self.owners[ty .. y1] {
+                    for cell in &mut row[tx .. x1] {
+                        *cell = owner;
+                    }
+                }
+
+                tx = x1;
+            }
+        }
+    }
+
+    fn is_mergeable(&self, place: &Place, x: usize, y: usize, material: Material) -> bool {
+        self.is_unclaimed_solid(place, x, y) && place.tile_at(TilePoint::new(x as TileCoord, y as TileCoord)).material == material
+    }
+
+    fn is_unclaimed_solid(&self, place: &Place, x: usize, y: usize) -> bool {
+        self.owners[y][x] == NO_COLLIDER && place.tile_at(TilePoint::new(x as TileCoord, y as TileCoord)).solid
+    }
+
+    /// Call `f` once with every collider that covers any of the given tiles.  Tiles outside the
+    /// place aren't cached, so those come through one at a time.
+    pub fn each_touching<F: FnMut(&Rect)>(&self, place: &Place, tiles: TileRect, mut f: F) {
+        // Big colliders span lots of tiles, so remember which ones we've already done
+        let mut seen = [0u32; MAX_TILE_COLLIDERS / 32];
+        for ty in tiles.min_y() .. tiles.max_y() {
+            for tx in tiles.min_x() .. tiles.max_x() {
+                let point = TilePoint::new(tx, ty);
+                if ! place.contains(point) {
+                    if place.tile_at(point).solid {
+                        f(&tile_to_world_rect(point));
+                    }
+                    continue;
+                }
+
+                match self.owners[ty as usize][tx as usize] {
+                    NO_COLLIDER => {}
+                    OVERFLOW_COLLIDER => f(&tile_to_world_rect(point)),
+                    owner => {
+                        let (word, bit) = (owner as usize / 32, 1 << (owner % 32));
+                        if seen[word] & bit == 0 {
+                            seen[word] |= bit;
+                            f(&self.colliders[owner as usize]);
+                        }
+                    }
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::data::places::{Outside, MAIN_TILESET};
+    use crate::geom::rect;
+
+    /// A little 4×4 room with a solid floor along the bottom.
+    fn room() -> Place {
+        let mut tiles = [[0; MAX_PLACE_DIMENSION]; MAX_PLACE_DIMENSION];
+        for tile in &mut tiles[3][.. 4] {
+            *tile = 2;
+        }
+        Place{
+            tileset: &MAIN_TILESET,
+            width: 4,
+            height: 4,
+            outside: Outside::Empty,
+            spawn: (0, 0),
+            triggers: &[],
+            props: &[],
+            tiles,
+        }
+    }
+
+    fn all_colliders(colliders: &TileColliders, place: &Place) -> ArrayVec<[Rect; 8]> {
+        let mut found = ArrayVec::new();
+        colliders.each_touching(place, euclid::rect(0, 0, 4, 4), |&collider| found.push(collider));
+        found
+    }
+
+    #[test]
+    fn changing_a_tile_rebuilds() {
+        let mut place = room();
+        let mut colliders = TileColliders::new();
+        assert!(colliders.refresh(&place));
+        assert_eq!(&all_colliders(&colliders, &place)[..], &[rect(0, 48, 64, 16)]);
+        assert!(! colliders.refresh(&place));
+
+        // Knock a hole in the floor; nothing notices until we say so
+        place.tiles[3][1] = 0;
+        assert!(! colliders.refresh(&place));
+        colliders.invalidate();
+        assert!(colliders.refresh(&place));
+        assert_eq!(&all_colliders(&colliders, &place)[..], &[rect(0, 48, 16, 16), rect(32, 48, 32, 16)]);
+
+        // And fill it back in, plus a block hanging in midair
+        place.tiles[3][1] = 2;
+        place.tiles[1][2] = 10;
+        colliders.invalidate();
+        assert!(colliders.refresh(&place));
+        assert_eq!(&all_colliders(&colliders, &place)[..], &[rect(32, 16, 16, 16), rect(0, 48, 64, 16)]);
+    }
+}
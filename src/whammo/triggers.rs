@@ -0,0 +1,94 @@
+/// Trigger volumes: shapes that never block anything, but notice when actors come and go.  Good
+/// for checkpoints, doors, hazards, camera regions, and so on.
+///
+/// Each trigger remembers who was inside it last time, so calling `update` once a frame turns
+/// plain overlap checks into enter/stay/exit events.
+
+use arrayvec::ArrayVec;
+
+use super::blockmap::{Blockmap, ShapeId, ShapeSet};
+use super::layers::Layers;
+use super::shapes::Shape;
+
+/// Most triggers that can exist at once.
+pub const MAX_TRIGGERS: usize = 16;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct TriggerId(u8);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum TriggerPhase {
+    /// The actor wasn't inside last update, and now it is
+    Enter,
+    /// The actor is still inside
+    Stay,
+    /// The actor was inside last update, and now it isn't (or it's gone entirely)
+    Exit,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct TriggerEvent<T> {
+    pub trigger: TriggerId,
+    pub actor: ShapeId,
+    pub phase: TriggerPhase,
+    /// Whatever was attached to the trigger when it was added
+    pub data: T,
+}
+
+struct Trigger<T> {
+    shape: Shape,
+    /// Which kinds of actors this trigger notices
+    layers: Layers,
+    data: T,
+    inside: ShapeSet,
+}
+
+pub struct Triggers<T> {
+    triggers: ArrayVec<[Trigger<T>; MAX_TRIGGERS]>,
+}
+
+impl<T: Copy> Triggers<T> {
+    pub fn new() -> Self {
+        Triggers{ triggers: ArrayVec::new() }
+    }
+
+    /// Add a trigger that notices actors in any of the given layers.  Returns None if there are
+    /// already too many.
+    pub fn add(&mut self, shape: Shape, layers: Layers, data: T) -> Option<TriggerId> {
+        let id = TriggerId(self.triggers.len() as u8);
+        self.triggers.try_push(Trigger{ shape, layers, data, inside: ShapeSet::new() }).ok()?;
+        Some(id)
+    }
+
+    /// Check every trigger against the actors, and call `f` with an event for each actor that's
+    /// entered, stayed in, or left a trigger since last time.  Events come out in trigger order,
+    /// then actor order.  `shape_of` looks up the shape for an id in `actors`; anything it can't
+    /// find counts as having left.
+    pub fn update<'a, S, F>(&mut self, actors: &Blockmap, shape_of: S, mut f: F)
+        where S: Fn(ShapeId) -> Option<&'a Shape>, F: FnMut(TriggerEvent<T>)
+    {
+        for (index, trigger) in self.triggers.iter_mut().enumerate() {
+            let mut inside = ShapeSet::new();
+            for id in actors.neighbors_in(&trigger.shape.bbox(), trigger.layers).iter() {
+                if shape_of(id).map_or(false, |shape| shape.overlaps(&trigger.shape)) {
+                    inside.insert(id);
+                }
+            }
+
+            let before = trigger.inside;
+            for actor in inside.union(before).iter() {
+                let phase = if ! before.contains(actor) {
+                    TriggerPhase::Enter
+                }
+                else if ! inside.contains(actor) {
+                    TriggerPhase::Exit
+                }
+                else {
+                    TriggerPhase::Stay
+                };
+                f(TriggerEvent{ trigger: TriggerId(index as u8), actor, phase, data: trigger.data });
+            }
+            trigger.inside = inside;
+        }
+    }
+}